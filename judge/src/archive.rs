@@ -0,0 +1,330 @@
+use std::collections::*;
+use std::error::Error;
+use std::io::{self, Read};
+
+use flate2::read::{DeflateDecoder, GzDecoder};
+
+/// tar、tar.gz、zip のアーカイブからファイルを読み出す
+///
+/// 形式は先頭のバイト列で判別する。ディレクトリの構造は無視してファイル名だけを使い、同じ名前のファイルがあればエラーにする。
+/// ファイルは `max_bytes` を超えた分を読まないので、上限を超えたかどうかは長さが `max_bytes` より大きいかで判断する。
+/// ファイルの数が `max_files` を超えるか、読んだ大きさの合計が `max_total_bytes` を超えたら、その時点で読むのをやめてエラーにする。
+pub fn read_files(archive: &[u8], max_files: usize, max_bytes: usize, max_total_bytes: usize) -> Result<BTreeMap<String, Vec<u8>>, Box<dyn Error>> {
+    let mut files = BTreeMap::new();
+    let mut total_bytes = 0;
+    let mut insert = |path: &str, content: Vec<u8>| -> Result<(), Box<dyn Error>> {
+        let Some(name) = file_name(path) else { return Ok(()) };
+        if files.len() >= max_files {
+            return Err(format!("the archive must contain at most {max_files} files").into());
+        }
+        total_bytes += content.len();
+        if total_bytes > max_total_bytes {
+            return Err(format!("the files in the archive must be at most {max_total_bytes} bytes in total").into());
+        }
+        if files.insert(name.to_string(), content).is_some() {
+            return Err(format!("duplicate file name: {name}").into());
+        }
+        Ok(())
+    };
+
+    if archive.starts_with(b"PK\x03\x04") || archive.starts_with(b"PK\x05\x06") {
+        read_zip(archive, max_files, max_bytes, &mut insert)?;
+    } else if archive.starts_with(&[0x1f, 0x8b]) {
+        read_tar(GzDecoder::new(archive), max_bytes, &mut insert)?;
+    } else {
+        read_tar(archive, max_bytes, &mut insert)?;
+    }
+    Ok(files)
+}
+
+/// パスのファイル名。ディレクトリと `.` で始まるファイルは読まないので `None` にする
+fn file_name(path: &str) -> Option<&str> {
+    let name = path.rsplit('/').next().unwrap_or_default();
+    (!name.is_empty() && !name.starts_with('.')).then_some(name)
+}
+
+fn read_tar(archive: impl Read, max_bytes: usize, insert: &mut impl FnMut(&str, Vec<u8>) -> Result<(), Box<dyn Error>>) -> Result<(), Box<dyn Error>> {
+    let mut archive = tar::Archive::new(archive);
+    for entry in archive.entries()? {
+        let entry = entry?;
+        if !entry.header().entry_type().is_file() {
+            continue;
+        }
+        let path = entry.path()?.to_string_lossy().to_string();
+        insert(&path, read_limited(entry, max_bytes)?)?;
+    }
+    Ok(())
+}
+
+/// zip の中央ディレクトリにあるファイルの情報
+struct ZipFile {
+    name: String,
+    flags: u16,
+    method: u16,
+    compressed_size: u32,
+    local_offset: u32,
+}
+
+/// zip の中央ディレクトリを読み、無圧縮と deflate で格納されたファイルを取り出す。暗号化と ZIP64 には対応しない
+///
+/// ファイルの数は中央ディレクトリで確かめ、多すぎるアーカイブは何も展開せずにエラーにする。
+fn read_zip(archive: &[u8], max_files: usize, max_bytes: usize, insert: &mut impl FnMut(&str, Vec<u8>) -> Result<(), Box<dyn Error>>) -> Result<(), Box<dyn Error>> {
+    let zip_files = read_central_directory(archive)?;
+    if zip_files.iter().filter(|zip_file| file_name(&zip_file.name).is_some() ).count() > max_files {
+        return Err(format!("the archive must contain at most {max_files} files").into());
+    }
+
+    for ZipFile { name, flags, method, compressed_size, local_offset } in zip_files {
+        if name.ends_with('/') {
+            continue;
+        }
+        if flags & 1 != 0 {
+            return Err(format!("{name}: encrypted zip entries are not supported").into());
+        }
+        if compressed_size == u32::MAX || local_offset == u32::MAX {
+            return Err(format!("{name}: ZIP64 archives are not supported").into());
+        }
+
+        let local_offset = local_offset as usize;
+        if u32_at(archive, local_offset)? != 0x04034b50 {
+            return Err(format!("broken zip archive: invalid local header of {name}").into());
+        }
+        let data_start = local_offset + 30 + u16_at(archive, local_offset + 26)? as usize + u16_at(archive, local_offset + 28)? as usize;
+        let data = archive.get(data_start .. data_start + compressed_size as usize).ok_or_else(|| format!("broken zip archive: truncated data of {name}") )?;
+        let content = match method {
+            0 => read_limited(data, max_bytes)?,
+            8 => read_limited(DeflateDecoder::new(data), max_bytes)?,
+            _ => return Err(format!("{name}: compression method {method} is not supported (use stored or deflate)").into()),
+        };
+        insert(&name, content)?;
+    }
+    Ok(())
+}
+
+fn read_central_directory(archive: &[u8]) -> Result<Vec<ZipFile>, Box<dyn Error>> {
+    // 中央ディレクトリの終端レコードは末尾の 22 バイトと、最大 65535 バイトのコメントの中にある
+    let search_start = archive.len().saturating_sub(22 + 65535);
+    let end = (search_start ..= archive.len().saturating_sub(22)).rev()
+        .find(|&i| archive[i ..].starts_with(b"PK\x05\x06") )
+        .ok_or("broken zip archive: end of central directory is not found")?;
+    let entry_count = u16_at(archive, end + 10)? as usize;
+    let mut offset = u32_at(archive, end + 16)? as usize;
+
+    let mut zip_files = vec![];
+    for _ in 0 .. entry_count {
+        if u32_at(archive, offset)? != 0x02014b50 {
+            return Err("broken zip archive: invalid central directory".into());
+        }
+        let name_length = u16_at(archive, offset + 28)? as usize;
+        let extra_length = u16_at(archive, offset + 30)? as usize;
+        let comment_length = u16_at(archive, offset + 32)? as usize;
+        let name = archive.get(offset + 46 .. offset + 46 + name_length).ok_or("broken zip archive: truncated file name")?;
+        zip_files.push(ZipFile {
+            name: String::from_utf8_lossy(name).to_string(),
+            flags: u16_at(archive, offset + 8)?,
+            method: u16_at(archive, offset + 10)?,
+            compressed_size: u32_at(archive, offset + 20)?,
+            local_offset: u32_at(archive, offset + 42)?,
+        });
+        offset += 46 + name_length + extra_length + comment_length;
+    }
+    Ok(zip_files)
+}
+
+/// `max_bytes` より 1 バイトだけ多く読み、上限を超えたことが分かるようにする
+fn read_limited(reader: impl Read, max_bytes: usize) -> io::Result<Vec<u8>> {
+    let mut content = vec![];
+    reader.take(max_bytes as u64 + 1).read_to_end(&mut content)?;
+    Ok(content)
+}
+
+fn u16_at(bytes: &[u8], offset: usize) -> Result<u16, Box<dyn Error>> {
+    let bytes = bytes.get(offset .. offset + 2).ok_or("broken zip archive: unexpected end of data")?;
+    Ok(u16::from_le_bytes([bytes[0], bytes[1]]))
+}
+
+fn u32_at(bytes: &[u8], offset: usize) -> Result<u32, Box<dyn Error>> {
+    let bytes = bytes.get(offset .. offset + 4).ok_or("broken zip archive: unexpected end of data")?;
+    Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use flate2::write::{DeflateEncoder, GzEncoder};
+    use flate2::Compression;
+
+    use super::*;
+
+    struct ZipEntry {
+        name: &'static str,
+        flags: u16,
+        method: u16,
+        data: Vec<u8>,
+    }
+
+    fn stored(name: &'static str, content: &[u8]) -> ZipEntry {
+        ZipEntry { name, flags: 0, method: 0, data: content.to_vec() }
+    }
+
+    fn deflated(name: &'static str, content: &[u8]) -> ZipEntry {
+        let mut encoder = DeflateEncoder::new(vec![], Compression::default());
+        encoder.write_all(content).unwrap();
+        ZipEntry { name, flags: 0, method: 8, data: encoder.finish().unwrap() }
+    }
+
+    /// CRC は検査しないので 0 のままにする。フラグ 0x08 のエントリはローカルヘッダのサイズを 0 にしてデータディスクリプタを付ける
+    fn zip(entries: &[ZipEntry]) -> Vec<u8> {
+        let (mut archive, mut central) = (vec![], vec![]);
+        for entry in entries {
+            let size = entry.data.len() as u32;
+            let local_size = if entry.flags & 0x08 != 0 { 0 } else { size };
+            let local_offset = archive.len() as u32;
+            archive.extend(0x04034b50u32.to_le_bytes());
+            for value in [20, entry.flags, entry.method, 0, 0] {
+                archive.extend(u16::to_le_bytes(value));
+            }
+            for value in [0, local_size, local_size] {
+                archive.extend(u32::to_le_bytes(value));
+            }
+            archive.extend((entry.name.len() as u16).to_le_bytes());
+            archive.extend(0u16.to_le_bytes());
+            archive.extend(entry.name.as_bytes());
+            archive.extend(&entry.data);
+            if entry.flags & 0x08 != 0 {
+                for value in [0x08074b50, 0, size, size] {
+                    archive.extend(u32::to_le_bytes(value));
+                }
+            }
+
+            central.extend(0x02014b50u32.to_le_bytes());
+            for value in [20, 20, entry.flags, entry.method, 0, 0] {
+                central.extend(u16::to_le_bytes(value));
+            }
+            for value in [0, size, size] {
+                central.extend(u32::to_le_bytes(value));
+            }
+            for value in [entry.name.len() as u16, 0, 0, 0, 0] {
+                central.extend(u16::to_le_bytes(value));
+            }
+            for value in [0, local_offset] {
+                central.extend(u32::to_le_bytes(value));
+            }
+            central.extend(entry.name.as_bytes());
+        }
+        let central_offset = archive.len() as u32;
+        archive.extend(&central);
+        archive.extend(0x06054b50u32.to_le_bytes());
+        for value in [0, 0, entries.len() as u16, entries.len() as u16] {
+            archive.extend(u16::to_le_bytes(value));
+        }
+        for value in [central.len() as u32, central_offset] {
+            archive.extend(u32::to_le_bytes(value));
+        }
+        archive.extend(0u16.to_le_bytes());
+        archive
+    }
+
+    fn tar(files: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut builder = tar::Builder::new(vec![]);
+        for (path, content) in files {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(content.len() as u64);
+            header.set_mode(0o644);
+            builder.append_data(&mut header, path, *content).unwrap();
+        }
+        builder.into_inner().unwrap()
+    }
+
+    fn names(files: &BTreeMap<String, Vec<u8>>) -> Vec<&str> {
+        files.keys().map(String::as_str).collect()
+    }
+
+    #[test]
+    fn zip_reads_stored_and_deflated_entries_by_base_name() {
+        let archive = zip(&[
+            stored("tests/", b""),
+            stored("tests/1.in", b"1 2\n"),
+            deflated("tests/1.out", b"3\n"),
+            stored("tests/.hidden", b"x"),
+            stored("../../2.in", b"5 6\n"),
+        ]);
+        let files = read_files(&archive, 10, 100, 1000).unwrap();
+        assert_eq!(names(&files), ["1.in", "1.out", "2.in"]);
+        assert_eq!(files["1.out"], b"3\n");
+        assert_eq!(files["2.in"], b"5 6\n");
+    }
+
+    #[test]
+    fn zip_uses_central_directory_sizes_for_data_descriptors() {
+        let mut entry = deflated("1.in", &b"1 2\n".repeat(10));
+        entry.flags |= 0x08;
+        let files = read_files(&zip(&[entry, stored("1.out", b"3\n")]), 10, 100, 1000).unwrap();
+        assert_eq!(files["1.in"], b"1 2\n".repeat(10));
+        assert_eq!(files["1.out"], b"3\n");
+    }
+
+    #[test]
+    fn oversized_entries_are_truncated_one_byte_past_the_limit() {
+        let files = read_files(&zip(&[stored("big.in", &[b'a'; 20]), deflated("big.out", &[b'b'; 20])]), 10, 10, 1000).unwrap();
+        assert_eq!(files["big.in"].len(), 11);
+        assert_eq!(files["big.out"].len(), 11);
+        let files = read_files(&tar(&[("big.in", &[b'a'; 20])]), 10, 10, 1000).unwrap();
+        assert_eq!(files["big.in"].len(), 11);
+    }
+
+    #[test]
+    fn zip_rejects_unsupported_and_broken_entries() {
+        let error = |archive: Vec<u8>| read_files(&archive, 10, 100, 1000).unwrap_err().to_string();
+        assert!(error(zip(&[stored("a/1.in", b"1"), stored("b/1.in", b"2")])).contains("duplicate file name: 1.in"));
+        assert!(error(zip(&[ZipEntry { method: 12, ..stored("1.in", b"1") }])).contains("compression method 12"));
+        assert!(error(zip(&[ZipEntry { flags: 1, ..stored("1.in", b"1") }])).contains("encrypted"));
+
+        let mut archive = zip(&[stored("1.in", b"12345")]);
+        // 中央ディレクトリの圧縮後サイズをデータより大きくする
+        let central = archive.windows(4).position(|window| window == b"PK\x01\x02" ).unwrap();
+        archive[central + 20 .. central + 24].copy_from_slice(&1000u32.to_le_bytes());
+        assert!(error(archive).contains("truncated data of 1.in"));
+
+        let archive = zip(&[stored("1.in", b"1")]);
+        assert!(error(archive[.. archive.len() - 1].to_vec()).contains("end of central directory"));
+    }
+
+    #[test]
+    fn zip_checks_the_file_count_before_inflating() {
+        // 圧縮方式が不明なエントリを展開しようとすれば別のエラーになる
+        let entries = ["1.in", "1.out", "2.in"].map(|name| ZipEntry { method: 12, ..stored(name, b"1") });
+        let error = read_files(&zip(&entries), 2, 100, 1000).unwrap_err().to_string();
+        assert!(error.contains("at most 2 files"), "{error}");
+        // ディレクトリと隠しファイルは数えない
+        let archive = zip(&[stored("tests/", b""), stored("tests/.keep", b""), stored("1.in", b"1"), stored("1.out", b"1")]);
+        assert_eq!(read_files(&archive, 2, 100, 1000).unwrap().len(), 2);
+        let error = read_files(&tar(&[("1.in", b"1"), ("1.out", b"1"), ("2.in", b"2")]), 2, 100, 1000).unwrap_err().to_string();
+        assert!(error.contains("at most 2 files"), "{error}");
+    }
+
+    #[test]
+    fn total_size_is_capped() {
+        let archive = zip(&[deflated("1.in", &[b'a'; 10]), deflated("1.out", &[b'b'; 10]), deflated("2.in", &[b'c'; 10])]);
+        assert_eq!(read_files(&archive, 10, 10, 30).unwrap().len(), 3);
+        let error = read_files(&archive, 10, 10, 25).unwrap_err().to_string();
+        assert!(error.contains("at most 25 bytes in total"), "{error}");
+        let error = read_files(&tar(&[("1.in", &[b'a'; 10]), ("1.out", &[b'b'; 10])]), 10, 10, 15).unwrap_err().to_string();
+        assert!(error.contains("at most 15 bytes in total"), "{error}");
+    }
+
+    #[test]
+    fn tar_and_tar_gz_use_base_names() {
+        let archive = tar(&[("sample/1.in", b"1 2\n"), ("sample/1.out", b"3\n"), ("sample/.keep", b"")]);
+        let files = read_files(&archive, 10, 100, 1000).unwrap();
+        assert_eq!(names(&files), ["1.in", "1.out"]);
+
+        let mut encoder = GzEncoder::new(vec![], Compression::default());
+        encoder.write_all(&archive).unwrap();
+        assert_eq!(read_files(&encoder.finish().unwrap(), 10, 100, 1000).unwrap(), files);
+
+        let archive = tar(&[("a/1.in", b"1"), ("b/1.in", b"2")]);
+        assert!(read_files(&archive, 10, 100, 1000).unwrap_err().to_string().contains("duplicate file name"));
+    }
+}
@@ -0,0 +1,250 @@
+use std::collections::*;
+use std::sync::Mutex;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+use actix_web::http::header;
+use actix_web::HttpRequest;
+use once_cell::sync::Lazy;
+
+use crate::config::*;
+use crate::error::{ApiError, ErrorCode};
+use crate::tls::ClientIdentity;
+
+/// API キーごとのレートリミットの状態: (残りのリクエスト数, 最終更新時刻)
+static RATE_LIMITS: Lazy<Mutex<HashMap<String, (f64, Instant)>>> = Lazy::new(|| Mutex::new(HashMap::new()) );
+
+/// API キーごとの実行時間（経過時間）の合計: (日付, ミリ秒)
+static RUN_TIME_USAGE: Lazy<Mutex<HashMap<String, (u64, u64)>>> = Lazy::new(|| Mutex::new(HashMap::new()) );
+
+const SECONDS_PER_DAY: u64 = 24 * 60 * 60;
+
+/// リクエストを送ったクライアント
+pub struct Client {
+    /// API キーの名前。認証が無効のときは接続元の IP アドレス
+    pub name: String,
+    key: Option<&'static ApiKeyConfig>,
+}
+
+impl Client {
+    pub fn has_scope(&self, scope: Scope) -> bool {
+        match self.key {
+            Some(key) => key.scopes.contains(&Scope::Admin) || key.scopes.contains(&scope),
+            // 認証が無効のときは、管理用の操作以外をすべて許可する
+            None => scope != Scope::Admin,
+        }
+    }
+
+    /// `admin` スコープを持つ API キーかどうか。認証が無効のときは誰も管理者ではない
+    pub fn is_admin(&self) -> bool {
+        self.key.is_some_and(|key| key.scopes.contains(&Scope::Admin) )
+    }
+
+    /// `owner` の提出を参照・操作できるかどうか
+    pub fn can_access(&self, owner: &str) -> bool {
+        self.key.is_none() || self.name == owner || self.has_scope(Scope::Admin)
+    }
+}
+
+/// API キーを探す。応答時間から API キーを推測されないよう、すべての API キーと一定時間で比較する
+fn find_key(key: &str) -> Option<(&'static String, &'static ApiKeyConfig)> {
+    let mut found = None;
+    for (name, config) in &CONFIG.server.auth.keys {
+        if config.key.as_ref().is_some_and(|expected| constant_time_eq(expected.as_bytes(), key.as_bytes()) ) {
+            found = Some((name, config));
+        }
+    }
+    found
+}
+
+/// 最初に異なるバイトの位置によらない時間で比較する。長さが異なれば直ちに `false` を返す
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| std::hint::black_box(diff | (x ^ y)) ) == 0
+}
+
+/// リクエストの API キーを検証し、レートリミットを適用する
+pub fn authorize(req: &HttpRequest, scope: Scope) -> Result<Client, ApiError> {
+    if !CONFIG.server.auth.enabled {
+        // Unix ドメインソケットからの接続には接続元のアドレスがない
+        let name = req.peer_addr().map(|addr| addr.ip().to_string() ).unwrap_or_else(|| "unix".to_string() );
+        if scope == Scope::Admin {
+            return Err(ApiError::new(ErrorCode::Forbidden, "admin endpoints require authentication to be enabled"));
+        }
+        return Ok(Client { name, key: None });
+    }
+
+    // API キーがなければ、クライアント証明書に対応する API キーを使う
+    let key = req.headers().get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok() )
+        .and_then(|value| value.strip_prefix("Bearer ") )
+        .map(str::trim);
+    let (name, config) = match (key, req.conn_data::<ClientIdentity>()) {
+        (Some(key), _) => find_key(key).ok_or_else(|| ApiError::new(ErrorCode::Unauthorized, "invalid API key") )?,
+        (None, Some(ClientIdentity(identity))) => CONFIG.server.auth.keys.iter()
+            .find(|(_, config)| config.client_cert.as_ref() == Some(identity) )
+            .ok_or_else(|| ApiError::new(ErrorCode::Unauthorized, format!("client certificate {identity} is not associated with any API key")) )?,
+        (None, None) => return Err(ApiError::new(ErrorCode::Unauthorized, "API key or client certificate is required")),
+    };
+
+    let client = Client { name: name.clone(), key: Some(config) };
+    if !client.has_scope(scope) {
+        let scope = format!("{scope:?}").to_lowercase();
+        return Err(ApiError::new(ErrorCode::Forbidden, format!("API key {name} does not have the {scope} scope")));
+    }
+    if let Some(rate_limit) = config.rate_limit {
+        check_rate_limit(name, rate_limit)?;
+    }
+    Ok(client)
+}
+
+/// トークンバケットで 1 分あたりのリクエスト数を制限する
+fn check_rate_limit(name: &str, rate_limit: u32) -> Result<(), ApiError> {
+    let capacity = rate_limit as f64;
+    let per_second = capacity / 60.0;
+    let now = Instant::now();
+
+    let mut rate_limits = RATE_LIMITS.lock().unwrap();
+    let (tokens, updated) = rate_limits.entry(name.to_string()).or_insert((capacity, now));
+    *tokens = (*tokens + (now - *updated).as_secs_f64() * per_second).min(capacity);
+    *updated = now;
+    if *tokens < 1.0 {
+        let retry_after = ((1.0 - *tokens) / per_second).ceil() as u64;
+        return Err(ApiError::new(ErrorCode::RateLimited, format!("at most {rate_limit} requests per minute are allowed")).with_retry_after(retry_after));
+    }
+    *tokens -= 1.0;
+    Ok(())
+}
+
+/// 同時に処理中の提出の数と、その日の実行時間の合計を制限する
+///
+/// `active` はクライアントの待機中またはジャッジ中の提出の数。
+pub fn check_submission_quota(client: &Client, active: usize) -> Result<(), ApiError> {
+    let Some(config) = client.key else { return Ok(()) };
+
+    if let Some(max_concurrent) = config.max_concurrent {
+        if active >= max_concurrent {
+            return Err(ApiError::new(ErrorCode::QuotaExceeded, format!("at most {max_concurrent} submissions can be judged at the same time")).with_retry_after(CONFIG.program.time_limit));
+        }
+    }
+
+    if let Some(daily_run_ms) = config.daily_run_ms {
+        let (today, seconds_until_tomorrow) = today();
+        let used = match RUN_TIME_USAGE.lock().unwrap().get(&client.name) {
+            Some(&(day, used)) if day == today => used,
+            _ => 0,
+        };
+        if used >= daily_run_ms {
+            return Err(ApiError::new(ErrorCode::QuotaExceeded, format!("daily execution time budget of {daily_run_ms} ms is exhausted")).with_retry_after(seconds_until_tomorrow));
+        }
+    }
+
+    Ok(())
+}
+
+/// クライアントのプログラムの実行時間を記録する。CPU 時間ではなく、テストケースごとに計った経過時間を使う
+pub fn record_run_time(client: &str, time_ms: u64) {
+    if !CONFIG.server.auth.enabled || CONFIG.server.auth.keys.get(client).is_none_or(|config| config.daily_run_ms.is_none() ) {
+        return;
+    }
+    let (today, _) = today();
+    let mut usage = RUN_TIME_USAGE.lock().unwrap();
+    let (day, used) = usage.entry(client.to_string()).or_insert((today, 0));
+    if *day != today {
+        (*day, *used) = (today, 0);
+    }
+    *used += time_ms;
+}
+
+/// UTC での日付と、翌日までの秒数
+fn today() -> (u64, u64) {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
+    (now / SECONDS_PER_DAY, SECONDS_PER_DAY - now % SECONDS_PER_DAY)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use actix_web::test::TestRequest;
+
+    use super::*;
+
+    fn request(key: Option<&str>) -> HttpRequest {
+        let request = TestRequest::default();
+        match key {
+            Some(key) => request.insert_header((header::AUTHORIZATION, format!("Bearer {key}"))),
+            None => request,
+        }.to_http_request()
+    }
+
+    fn error_code(result: Result<Client, ApiError>) -> ErrorCode {
+        result.err().unwrap().code
+    }
+
+    #[test]
+    fn constant_time_eq_compares_bytes() {
+        assert!(constant_time_eq(b"alice-key", b"alice-key"));
+        assert!(!constant_time_eq(b"alice-key", b"alice-kez"));
+        assert!(!constant_time_eq(b"alice-key", b"alice-key2"));
+        assert!(constant_time_eq(b"", b""));
+    }
+
+    #[test]
+    fn keys_are_checked() {
+        init_test_config();
+        assert_eq!(error_code(authorize(&request(None), Scope::Status)), ErrorCode::Unauthorized);
+        assert_eq!(error_code(authorize(&request(Some("alice-kez")), Scope::Status)), ErrorCode::Unauthorized);
+        assert_eq!(error_code(authorize(&request(Some("alice")), Scope::Status)), ErrorCode::Unauthorized);
+        let client = authorize(&request(Some("alice-key")), Scope::Submit).unwrap();
+        assert_eq!(client.name, "alice");
+        assert!(!client.is_admin());
+    }
+
+    #[test]
+    fn scopes_are_checked() {
+        init_test_config();
+        assert_eq!(error_code(authorize(&request(Some("alice-key")), Scope::Admin)), ErrorCode::Forbidden);
+        assert_eq!(error_code(authorize(&request(Some("alice-key")), Scope::Metrics)), ErrorCode::Forbidden);
+        // admin スコープはすべての操作を許可する
+        for scope in [Scope::Submit, Scope::Status, Scope::Metrics, Scope::Admin] {
+            assert!(authorize(&request(Some("root-key")), scope).is_ok());
+        }
+
+        let alice = authorize(&request(Some("alice-key")), Scope::Status).unwrap();
+        let root = authorize(&request(Some("root-key")), Scope::Status).unwrap();
+        assert!(alice.can_access("alice") && !alice.can_access("carol"));
+        assert!(root.is_admin() && root.can_access("carol"));
+    }
+
+    #[test]
+    fn clients_without_keys_cannot_use_admin_scope() {
+        let client = Client { name: "192.0.2.1".to_string(), key: None };
+        assert!(client.has_scope(Scope::Submit) && client.has_scope(Scope::Metrics));
+        assert!(!client.has_scope(Scope::Admin));
+        assert!(!client.is_admin());
+        assert!(client.can_access("192.0.2.2"));
+    }
+
+    #[test]
+    fn rate_limit_rejects_requests_over_the_limit() {
+        init_test_config();
+        assert!(authorize(&request(Some("bob-key")), Scope::Status).is_ok());
+        assert!(authorize(&request(Some("bob-key")), Scope::Status).is_ok());
+        let error = authorize(&request(Some("bob-key")), Scope::Status).err().unwrap();
+        assert_eq!(error.code, ErrorCode::RateLimited);
+        assert!(error.retry_after.is_some_and(|seconds| seconds > 0 ));
+    }
+
+    #[test]
+    fn submission_quota_limits_concurrency_and_run_time() {
+        init_test_config();
+        let alice = authorize(&request(Some("alice-key")), Scope::Submit).unwrap();
+        assert!(check_submission_quota(&alice, 0).is_ok());
+        assert_eq!(check_submission_quota(&alice, 1).unwrap_err().code, ErrorCode::QuotaExceeded);
+
+        record_run_time("alice", 60);
+        assert!(check_submission_quota(&alice, 0).is_ok());
+        record_run_time("alice", 40);
+        let error = check_submission_quota(&alice, 0).unwrap_err();
+        assert_eq!(error.code, ErrorCode::QuotaExceeded);
+        assert!(error.message.contains("100 ms"), "{}", error.message);
+    }
+}
\ No newline at end of file
@@ -0,0 +1,169 @@
+/*
+提出プログラムから利用できるクレートをビルドするためのプログラム。
+
+Usage: build_deps [--vendor] [CONFIG_TOML] [DEPENDENCIES_TOML]
+
+- DEPENDENCIES_TOML (既定値: dependencies.toml) の [dependencies] に書かれたクレートをビルドする。
+  書式は Cargo.toml の [dependencies] と同じである。
+- ビルドは {dependency_dir}/build に作成した Cargo プロジェクトで行い、
+  {dependency_dir}/build/vendor にベンダリングされたレジストリのみを用いてオフラインで行う。
+- --vendor を指定した場合は、ビルドの前に cargo vendor でレジストリをベンダリングする（ネットワークが必要）。
+- {dependency_dir}/build の Cargo.toml, Cargo.lock, vendor は Cargo プロジェクトでの提出のビルドにも使われる。
+- ビルドしたライブラリは {dependency_dir} にコピーされ、
+  CONFIG_TOML (既定値: config.toml) の program.externs が対応する --extern の表に書き換えられる。
+*/
+
+fn main() -> Result<(), Box<dyn Error>> {
+    // 引数の取得
+    let (vendor, config_path, dependencies_path) = {
+        let mut vendor = false;
+        let mut paths = vec![];
+        for arg in args().skip(1) {
+            match arg.as_str() {
+                "--vendor" => vendor = true,
+                "-h" | "--help" => usage(),
+                _ => paths.push(arg),
+            }
+        }
+        let mut paths = paths.into_iter();
+        let config_path = paths.next().unwrap_or_else(|| "config.toml".to_string() );
+        let dependencies_path = paths.next().unwrap_or_else(|| "dependencies.toml".to_string() );
+        (vendor, config_path, dependencies_path)
+    };
+
+    // 設定の読み込み
+    let mut config = read_to_string(&config_path)?.parse::<Table>()?;
+    let dependency_dir = config.get("program")
+        .and_then(|program| program.get("dependency_dir") )
+        .and_then(Value::as_str)
+        .ok_or("program.dependency_dir is not set")?;
+    if dependency_dir.is_empty() {
+        return Err("program.dependency_dir is empty".into());
+    }
+    create_dir_all(dependency_dir)?;
+    let dependency_dir = canonicalize(dependency_dir)?;
+
+    let dependencies = read_to_string(&dependencies_path)?.parse::<Table>()?
+        .remove("dependencies")
+        .ok_or_else(|| format!("{dependencies_path} has no [dependencies] table") )?;
+
+    // ビルド用プロジェクトの作成
+    let project_dir = dependency_dir.join("build");
+    create_dir_all(project_dir.join("src"))?;
+    create_dir_all(project_dir.join(".cargo"))?;
+    let manifest = {
+        let mut package = Table::new();
+        package.insert("name".to_string(), Value::from("main"));
+        package.insert("version".to_string(), Value::from("0.1.0"));
+        package.insert("edition".to_string(), Value::from("2021"));
+        package.insert("publish".to_string(), Value::from(false));
+        let mut manifest = Table::new();
+        manifest.insert("package".to_string(), Value::from(package));
+        manifest.insert("dependencies".to_string(), dependencies);
+        manifest
+    };
+    write(project_dir.join("Cargo.toml"), manifest.to_string())?;
+    write(project_dir.join("src/lib.rs"), "")?;
+    write(project_dir.join(".cargo/config.toml"), "[source.crates-io]\nreplace-with = \"vendored-sources\"\n\n[source.vendored-sources]\ndirectory = \"vendor\"\n")?;
+
+    // ベンダリング
+    if vendor {
+        run_cargo(&project_dir, &["vendor", "--versioned-dirs", "vendor"])?;
+    }
+    if !project_dir.join("vendor").is_dir() {
+        return Err(format!("vendored registry {} does not exist; run with --vendor first", project_dir.join("vendor").display()).into());
+    }
+
+    // 直接依存しているパッケージと、その extern 名を取得
+    let metadata: JsonValue = serde_json::from_slice(&run_cargo(&project_dir, &["metadata", "--offline", "--format-version=1"])?)?;
+    let root = metadata["resolve"]["root"].as_str().ok_or("cargo metadata has no root package")?;
+    let direct_dependencies = metadata["resolve"]["nodes"].as_array()
+        .and_then(|nodes| nodes.iter().find(|node| node["id"] == root ) )
+        .and_then(|node| node["deps"].as_array() )
+        .ok_or("cargo metadata has no root node")?
+        .iter()
+        .filter_map(|dep| Some((dep["pkg"].as_str()?.to_string(), dep["name"].as_str()?.to_string())) )
+        .collect::<HashMap<_, _>>();
+
+    // ビルド
+    let messages = run_cargo(&project_dir, &["build", "--release", "--offline", "--message-format=json"])?;
+    let mut extern_files = BTreeMap::new();
+    let mut library_files = vec![];
+    for line in messages.split(|&c| c == b'\n' ) {
+        let Ok(message) = serde_json::from_slice::<JsonValue>(line) else { continue };
+        if message["reason"] != "compiler-artifact" || message["package_id"] == root {
+            continue;
+        }
+        let extern_name = message["package_id"].as_str().and_then(|id| direct_dependencies.get(id) );
+        let filenames = message["filenames"].as_array().into_iter().flatten().filter_map(JsonValue::as_str);
+        for filename in filenames.map(PathBuf::from).filter(|filename| is_library(filename) ) {
+            if let Some(name) = extern_name {
+                if filename.extension() != Some(OsStr::new("rmeta")) {
+                    extern_files.insert(name.clone(), filename.clone());
+                }
+            }
+            library_files.push(filename);
+        }
+    }
+
+    // ライブラリを依存ディレクトリへコピー
+    for entry in read_dir(&dependency_dir)? {
+        let path = entry?.path();
+        if path.is_file() && is_library(&path) {
+            remove_file(&path)?;
+        }
+    }
+    for path in &library_files {
+        copy(path, dependency_dir.join(path.file_name().unwrap()))?;
+    }
+
+    // --extern の表を書き出す
+    let mut externs = Table::new();
+    for (name, path) in extern_files {
+        let path = dependency_dir.join(path.file_name().unwrap());
+        println!("--extern {name}={}", path.display());
+        externs.insert(name, Value::from(path.to_string_lossy().into_owned()));
+    }
+    config.get_mut("program")
+        .and_then(Value::as_table_mut)
+        .ok_or("[program] is not a table")?
+        .insert("externs".to_string(), Value::from(externs));
+    write(&config_path, config.to_string())?;
+
+    Ok(())
+}
+
+fn is_library(path: &Path) -> bool {
+    matches!(path.extension().and_then(OsStr::to_str), Some("rlib" | "rmeta" | "so"))
+}
+
+fn run_cargo(project_dir: &Path, args: &[&str]) -> Result<Vec<u8>, Box<dyn Error>> {
+    println!("$ cargo {}", args.join(" "));
+    let output = Command::new("cargo")
+        .args(args)
+        .current_dir(project_dir)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::inherit())
+        .output()?;
+    if !output.status.success() {
+        return Err(format!("cargo {} failed: {}", args[0], output.status).into());
+    }
+    Ok(output.stdout)
+}
+
+fn usage() -> ! {
+    println!("Usage: {} [--vendor] [CONFIG_TOML] [DEPENDENCIES_TOML]", current_exe().unwrap().to_string_lossy());
+    println!("Note: CONFIG_TOML defaults to config.toml, DEPENDENCIES_TOML defaults to dependencies.toml");
+    println!("Note: --vendor downloads the crates into the vendored registry and requires network access");
+    exit(0)
+}
+
+use std::collections::*;
+use std::env::*;
+use std::error::*;
+use std::ffi::OsStr;
+use std::fs::*;
+use std::path::*;
+use std::process::*;
+use serde_json::Value as JsonValue;
+use toml::{Table, Value};
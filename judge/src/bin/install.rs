@@ -6,6 +6,7 @@ fn main() -> Result<(), Box<dyn Error>> {
     run_command(&format!("mkdir -p {install_dir}"))?;
     run_command(&format!("cp -T default.toml {install_dir}/config.toml"))?;
     run_command(&format!("cp -TR public {install_dir}/public"))?;
+    run_command(&format!("cp -T dependencies.toml {install_dir}/dependencies.toml"))?;
 
     run_command("cargo build --release --bin judge")?;
     run_command(&format!("cp -T target/release/judge {install_dir}/judge"))?;
@@ -16,13 +17,16 @@ fn main() -> Result<(), Box<dyn Error>> {
     run_command(&format!("sudo chown root:root {install_dir}/safe_run"))?;
     run_command(&format!("sudo chmod u+s {install_dir}/safe_run"))?;
 
+    run_command("cargo build --release --bin build_deps")?;
+    run_command(&format!("cp -T target/release/build_deps {install_dir}/build_deps"))?;
+
     Ok(())
 }
 
 fn prompt(ask: &str) -> Result<String, Box<dyn Error>> {
     use std::io::*;
     let mut stdout = stdout();
-    stdout.write(ask.as_bytes())?;
+    stdout.write_all(ask.as_bytes())?;
     stdout.flush()?;
     let mut input = String::new();
     stdin().read_to_string(&mut input)?;
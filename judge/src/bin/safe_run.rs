@@ -17,7 +17,7 @@ fn main() -> Result<(), Box<dyn Error>> {
         args.next(); // ignore program name
         let root_dir = args.next().unwrap_or_else(|| usage() );
         let command = CString::new(args.next().unwrap_or_else(|| usage() ))?;
-        let mut argv = args.map(|s| CString::new(s) ).collect::<Result<Vec<_>, _>>()?;
+        let mut argv = args.map(CString::new).collect::<Result<Vec<_>, _>>()?;
         argv.insert(0, command.clone());
         let envp = vec![CString::new("PATH=")?];
         (root_dir, command, argv, envp)
@@ -27,9 +27,9 @@ fn main() -> Result<(), Box<dyn Error>> {
     let parent_uid = {
         let parent_pid = Pid::parent();
         let mut parent_status = String::new();
-        File::open(&format!("/proc/{parent_pid}/status"))?.read_to_string(&mut parent_status)?;
+        File::open(format!("/proc/{parent_pid}/status"))?.read_to_string(&mut parent_status)?;
         let uid_row = parent_status.lines().find(|line| line.split_ascii_whitespace().next() == Some("Uid:") ).expect("couldn't get uid row from /proc/<caller_pid>/status");
-        uid_row.split_ascii_whitespace().skip(2).next().expect("effective uid").parse::<u32>()?
+        uid_row.split_ascii_whitespace().nth(2).expect("effective uid").parse::<u32>()?
     };
 
     // unshare
@@ -1,35 +1,615 @@
-use std::{fs::File, io::Read};
+use std::{cell::Cell, collections::{BTreeMap, HashMap, HashSet}, env, error::Error, ffi::OsString, fs::read_to_string, ops::Deref, os::unix::ffi::OsStrExt, path::{Path, PathBuf}, sync::{Mutex, RwLock}, time::{Duration, SystemTime, UNIX_EPOCH}};
 
-use once_cell::sync::Lazy;
+use actix_web::http::{header::HeaderName, Method, Uri};
 use serde::{Deserialize, Serialize};
-use toml::{from_str, Table};
+use toml::value::{Datetime, Offset};
+use toml::{Table, Value};
 
-pub static CONFIG: Lazy<Config> = Lazy::new(|| {
-    let mut config_toml = String::new();
-    File::open("config.toml").unwrap().read_to_string(&mut config_toml).unwrap();
-    from_str(&config_toml).unwrap()
-});
+/// 設定ファイルのパスの既定値
+pub const DEFAULT_CONFIG_PATH: &str = "config.toml";
+
+/// 環境変数で設定を上書きするときの接頭辞
+///
+/// `JUDGE_PROGRAM__TIME_LIMIT=5` のように、`__` で区切ったキーのパスで指定する。
+/// 最初のキーが設定ファイルの表（`program` など）でなく、`__` を含まない環境変数は設定と関係ないものとして無視する。
+pub const ENV_PREFIX: &str = "JUDGE_";
+
+/// 現在の設定
+///
+/// 再読み込みで差し替えられても古い設定への参照が無効にならないよう、読み込んだ設定はリークさせる。
+/// 再読み込みは稀なので、リークする量は問題にならない。
+static CURRENT_CONFIG: RwLock<Option<&'static Config>> = RwLock::new(None);
+
+/// 再読み込みのときに読む設定ファイル
+static CONFIG_PATH: Mutex<Option<PathBuf>> = Mutex::new(None);
+
+thread_local! {
+    /// `pin` によってこのスレッドで固定された設定
+    static PINNED_CONFIG: Cell<Option<&'static Config>> = const { Cell::new(None) };
+}
+
+/// 現在の設定。`init` されていなければ `config.toml` から読み込む
+pub static CONFIG: ConfigHandle = ConfigHandle;
+
+pub struct ConfigHandle;
+
+impl Deref for ConfigHandle {
+    type Target = Config;
+
+    fn deref(&self) -> &Config {
+        current()
+    }
+}
+
+fn current() -> &'static Config {
+    if let Some(config) = PINNED_CONFIG.with(Cell::get) {
+        return config;
+    }
+    if let Some(config) = *CURRENT_CONFIG.read().unwrap() {
+        return config;
+    }
+    let mut current = CURRENT_CONFIG.write().unwrap();
+    current.get_or_insert_with(|| Box::leak(Box::new(load(Path::new(DEFAULT_CONFIG_PATH)).unwrap_or_else(|err| panic!("{err}") ))) )
+}
+
+/// 読み込んだ設定を `CONFIG` として使う。`path` は再読み込みのときに読む
+pub fn init(config: Config, path: &Path) {
+    *CONFIG_PATH.lock().unwrap() = Some(path.to_path_buf());
+    *CURRENT_CONFIG.write().unwrap() = Some(Box::leak(Box::new(config)));
+}
+
+/// 設定ファイルを読み直して `CONFIG` を差し替える
+///
+/// 読み込みや検証に失敗した場合は、現在の設定のまま変更しない。
+pub fn reload() -> Result<(), Box<dyn Error>> {
+    let path = CONFIG_PATH.lock().unwrap().clone().unwrap_or_else(|| PathBuf::from(DEFAULT_CONFIG_PATH) );
+    let config = load(&path)?;
+    *CURRENT_CONFIG.write().unwrap() = Some(Box::leak(Box::new(config)));
+    Ok(())
+}
+
+/// `f` を実行する間、このスレッドから見える `CONFIG` を現在の設定に固定する
+///
+/// ジャッジ中の提出が途中で再読み込みの影響を受けないようにするために使う。
+pub fn pin<T>(f: impl FnOnce() -> T) -> T {
+    let config = current();
+    let previous = PINNED_CONFIG.with(|pinned| pinned.replace(Some(config)) );
+    let result = f();
+    PINNED_CONFIG.with(|pinned| pinned.set(previous) );
+    result
+}
+
+/// 設定ファイルを読み込み、環境変数による上書きと `server.auth.keys_path` の API キーを適用して検証する
+pub fn load(path: &Path) -> Result<Config, Box<dyn Error>> {
+    let config_toml = read_to_string(path).map_err(|err| format!("{}: {err}", path.display()) )?;
+    let mut table = config_toml.parse::<Table>().map_err(|err| format!("{}: {err}", path.display()) )?;
+    apply_env_overrides(&mut table, env::vars_os())?;
+    let mut config = table.try_into::<Config>().map_err(|err| format!("{}: {err}", path.display()) )?;
+    if let Some(keys_path) = &config.server.auth.keys_path {
+        let keys_toml = read_to_string(keys_path).map_err(|err| format!("{keys_path}: {err}") )?;
+        let keys = toml::from_str::<BTreeMap<String, ApiKeyConfig>>(&keys_toml).map_err(|err| format!("{keys_path}: {err}") )?;
+        config.server.auth.keys.extend(keys);
+    }
+    config.validate().map_err(|err| format!("{}: {err}", path.display()) )?;
+    Ok(config)
+}
+
+/// `JUDGE_` で始まる環境変数で設定を上書きする
+///
+/// 環境変数には UTF-8 でないものもあるので、接頭辞が合わないものは名前を解釈せずに飛ばす。
+/// 接頭辞が合うのに名前か上書きする値が UTF-8 でなければ、設定のエラーにする。
+fn apply_env_overrides(table: &mut Table, vars: impl Iterator<Item = (OsString, OsString)>) -> Result<(), String> {
+    for (name, value) in vars {
+        if !name.as_bytes().starts_with(ENV_PREFIX.as_bytes()) {
+            continue;
+        }
+        let name = name.into_string().map_err(|name| format!("{}: the name of the environment variable is not valid UTF-8", name.to_string_lossy()) )?;
+        let key_path = &name[ENV_PREFIX.len() ..];
+        let section = key_path.split_once("__").map(|(section, _)| section.to_lowercase() );
+        if section.is_some_and(|section| table.get(&section).is_some_and(Value::is_table) ) {
+            let value = value.into_string().map_err(|_| format!("{name}: the value is not valid UTF-8") )?;
+            apply_override(table, key_path, &value).map_err(|err| format!("{name}: {err}") )?;
+        }
+    }
+    Ok(())
+}
+
+/// `key_path` が指す値を `value` で置き換える
+///
+/// 値は TOML の値として解釈できればその値に、できなければ文字列になる。
+/// キーは表に同じ綴りのキーがあればそのまま使い、なければ小文字にする。
+/// そのため大文字を含む API キーの名前などは、設定ファイルにあるものだけを上書きできる。
+fn apply_override(table: &mut Table, key_path: &str, value: &str) -> Result<(), String> {
+    let keys = key_path.split("__").collect::<Vec<_>>();
+    let (last, parents) = keys.split_last().filter(|(last, _)| !last.is_empty() ).ok_or("empty key")?;
+    let mut table = table;
+    for key in parents {
+        let key = table_key(table, key);
+        table = table.entry(&key)
+            .or_insert_with(|| Value::Table(Table::new()) )
+            .as_table_mut()
+            .ok_or_else(|| format!("{key} is not a table") )?;
+    }
+    let value = format!("value = {value}").parse::<Table>().ok()
+        .and_then(|mut parsed| parsed.remove("value") )
+        .unwrap_or_else(|| Value::from(value) );
+    table.insert(table_key(table, last), value);
+    Ok(())
+}
+
+fn table_key(table: &Table, key: &str) -> String {
+    if table.contains_key(key) { key.to_string() } else { key.to_lowercase() }
+}
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
 pub struct Config {
     pub program: ProgramConfig,
     pub server: ServerConfig,
+    pub problem: ProblemConfig,
+    pub contest: ContestsConfig,
+}
+
+impl Config {
+    /// 型だけでは表せない制約を確認する
+    fn validate(&self) -> Result<(), String> {
+        if !self.program.profiles.contains_key(&self.program.default_profile) {
+            return Err(format!("program.default_profile: compile profile {} is not defined in [program.profiles]", self.program.default_profile));
+        }
+        if self.program.time_limit == 0 {
+            return Err("program.time_limit must be positive".to_string());
+        }
+        if self.program.build_time_limit == 0 {
+            return Err("program.build_time_limit must be positive".to_string());
+        }
+        for (name, path) in [("program.source_path", &self.program.source_path), ("program.execute_dir", &self.program.execute_dir), ("program.project_dir", &self.program.project_dir)] {
+            if !path.contains("{submission_id}") {
+                return Err(format!("{name} must contain {{submission_id}}"));
+            }
+        }
+        if self.problem.max_archive_bytes < self.problem.max_test_bytes {
+            return Err("problem.max_archive_bytes must be at least problem.max_test_bytes".to_string());
+        }
+        if self.server.scheduler.default_weight == 0 || self.server.scheduler.client_weights.values().any(|&weight| weight == 0 ) {
+            return Err("server.scheduler: weights must be positive".to_string());
+        }
+        if self.server.listeners.is_empty() {
+            return Err("server.listeners: at least one listener is required".to_string());
+        }
+        let (mut key_names, mut cert_names) = (HashMap::new(), HashMap::new());
+        for (name, key) in &self.server.auth.keys {
+            if key.key.is_none() && key.client_cert.is_none() {
+                return Err(format!("server.auth: API key {name} needs key or client_cert"));
+            }
+            if let Some(other) = key.key.as_ref().and_then(|key| key_names.insert(key, name) ) {
+                return Err(format!("server.auth: API keys {other} and {name} have the same key"));
+            }
+            if let Some(other) = key.client_cert.as_ref().and_then(|client_cert| cert_names.insert(client_cert, name) ) {
+                return Err(format!("server.auth: API keys {other} and {name} have the same client_cert"));
+            }
+        }
+        self.server.cors.validate()?;
+        for (name, contest) in &self.contest.contests {
+            contest.validate().map_err(|err| format!("contest.contests.{name}: {err}") )?;
+        }
+        for (name, file) in &self.server.public_files {
+            if !file.is_str() {
+                return Err(format!("server.public_files.\"{name}\" must be a string"));
+            }
+        }
+        Ok(())
+    }
 }
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
 pub struct ProgramConfig {
     pub source_path: String,
     pub execute_dir: String,
+    pub project_dir: String,
     pub dependency_dir: String,
     pub time_limit: u64,
+    /// Cargo プロジェクトのビルドの制限時間（秒）
+    pub build_time_limit: u64,
+    /// ジャッジ側の問題で実行に失敗したときに再試行する回数
+    pub internal_error_retries: u32,
     pub externs: Table,
+    pub default_profile: String,
+    pub profiles: BTreeMap<String, CompileProfile>,
+}
+
+/// コンパイル時のオプションの組
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct CompileProfile {
+    /// `-C` で渡すコード生成オプション
+    pub codegen_options: Vec<String>,
+    /// その他の rustc の引数
+    pub flags: Vec<String>,
+    /// `externs` のクレートを使えるようにするかどうか
+    pub externs: bool,
+    /// rustup のツールチェイン名（例: nightly）。省略時は既定のツールチェインを使う
+    pub toolchain: Option<String>,
 }
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
 pub struct ServerConfig {
-    pub addr_port: String,
-    pub ssl_cert_path: String,
-    pub ssl_key_path: String,
+    /// 待ち受けるアドレスの一覧。少なくとも 1 つ必要
+    pub listeners: Vec<ListenerConfig>,
     pub public_files: Table,
     pub keep_submission_time: u64,
+    pub limits: LimitsConfig,
+    pub scheduler: SchedulerConfig,
+    pub auth: AuthConfig,
+    pub log: LogConfig,
+    pub cors: CorsConfig,
+}
+
+/// 待ち受けるアドレスと方式
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub enum ListenerConfig {
+    Https {
+        addr_port: String,
+        ssl_cert_path: String,
+        /// PKCS#8, PKCS#1 (RSA), SEC1 (EC) のいずれかの形式の秘密鍵
+        ssl_key_path: String,
+        /// クライアント証明書による認証 (mTLS)
+        client_auth: Option<ClientAuthConfig>,
+    },
+    Http {
+        addr_port: String,
+    },
+    /// Unix ドメインソケット
+    Unix {
+        path: String,
+        /// ソケットファイルのパーミッション（例: 0o660）
+        mode: Option<u32>,
+    },
+}
+
+/// クライアント証明書の検証の設定
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct ClientAuthConfig {
+    /// クライアント証明書を発行する、信頼する CA の証明書
+    pub ca_path: String,
+    /// true のときはクライアント証明書のない接続を拒否する
+    pub required: bool,
+}
+
+/// 提出の大きさの制限
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct LimitsConfig {
+    /// リクエストボディの最大バイト数
+    pub payload_bytes: usize,
+    /// ソースコードの合計の最大バイト数
+    pub source_bytes: usize,
+    /// 入力の最大個数
+    pub input_count: usize,
+    /// 入力の合計の最大バイト数
+    pub input_bytes: usize,
+}
+
+/// 待機中の提出の処理順の設定
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct SchedulerConfig {
+    /// クライアントの重みの既定値
+    pub default_weight: u64,
+    /// クライアントごとの重み。重みに比例した割合で提出が処理される
+    pub client_weights: BTreeMap<String, u64>,
+}
+
+/// API キーによる認証の設定
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct AuthConfig {
+    /// false のときは認証を行わず、すべてのリクエストを受け付ける
+    pub enabled: bool,
+    /// 名前から API キーの設定への対応。読み込み後は `keys_path` のファイルのキーも含む
+    pub keys: BTreeMap<String, ApiKeyConfig>,
+    /// `keys` と同じ形式で API キーを書いた TOML ファイル（省略可）
+    pub keys_path: Option<String>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct ApiKeyConfig {
+    /// `Authorization: Bearer <key>` で送られるキー
+    pub key: Option<String>,
+    /// このキーとして扱うクライアント証明書のサブジェクトの CN
+    pub client_cert: Option<String>,
+    pub scopes: Vec<Scope>,
+    /// 1 分あたりのリクエスト数の上限
+    pub rate_limit: Option<u32>,
+    /// 同時に待機中またはジャッジ中にできる提出の数の上限
+    pub max_concurrent: Option<usize>,
+    /// 1 日 (UTC) あたりの実行時間の合計の上限（ミリ秒）。CPU 時間ではなく、テストケースごとの経過時間を合計する
+    pub daily_run_ms: Option<u64>,
+}
+
+/// API キーに許可する操作
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Scope {
+    /// 提出と、自身の提出の取り消し
+    Submit,
+    /// 自身の提出の状況の取得
+    Status,
+    /// `/metrics` の取得
+    Metrics,
+    /// すべての操作
+    Admin,
+}
+
+/// 問題の置き場所
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct ProblemConfig {
+    /// 問題ごとのディレクトリ `<dir>/<problem_id>/` を置くディレクトリ
+    pub dir: String,
+    /// 1 つの問題のテストケースの数の上限
+    pub max_test_count: usize,
+    /// テストケースのファイル 1 つの大きさの上限
+    pub max_test_bytes: usize,
+    /// アーカイブでまとめて送るテストケースのファイルの大きさの合計の上限
+    pub max_archive_bytes: usize,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct ContestsConfig {
+    /// コンテストへの提出の判定を記録する JSON Lines のファイル
+    pub submissions_path: String,
+    /// 再ジャッジのために、コンテストへの提出のソースコードを保存するディレクトリ
+    pub sources_dir: String,
+    /// コンテスト ID からコンテストの設定への対応
+    pub contests: BTreeMap<String, ContestConfig>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct ContestConfig {
+    pub title: String,
+    /// 開始時刻。タイムゾーンを含めて書く（例: 2026-10-20T21:00:00+09:00）
+    pub start_time: Datetime,
+    pub end_time: Datetime,
+    /// 出題する問題の ID。この順に表示する
+    pub problems: Vec<String>,
+    /// 参加者の API キーの名前。認証が無効のときは接続元の IP アドレス
+    pub participants: Vec<String>,
+    pub scoring: ScoringRule,
+    /// 不正解 1 回あたりのペナルティ（分）。IOI 形式では使わない
+    pub penalty_minutes: u64,
+    /// 終了の何分前に順位表を凍結するか。省略時は凍結しない
+    pub freeze_minutes: Option<u64>,
+}
+
+impl ContestConfig {
+    pub fn start(&self) -> SystemTime {
+        system_time(&self.start_time).unwrap()
+    }
+
+    pub fn end(&self) -> SystemTime {
+        system_time(&self.end_time).unwrap()
+    }
+
+    /// 順位表を凍結する時刻
+    pub fn freeze(&self) -> Option<SystemTime> {
+        self.freeze_minutes.map(|minutes| self.end() - Duration::from_secs(minutes * 60) )
+    }
+
+    /// 提出を受け付ける期間かどうか
+    pub fn is_running(&self, now: SystemTime) -> bool {
+        self.start() <= now && now < self.end()
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        let start = system_time(&self.start_time).ok_or("start_time must be a date and time with a time zone")?;
+        let end = system_time(&self.end_time).ok_or("end_time must be a date and time with a time zone")?;
+        if start >= end {
+            return Err("end_time must be after start_time".to_string());
+        }
+        if self.freeze_minutes.is_some_and(|minutes| Duration::from_secs(minutes * 60) > end.duration_since(start).unwrap_or_default() ) {
+            return Err("freeze_minutes must not be longer than the contest".to_string());
+        }
+        let mut problems = HashSet::new();
+        for problem in &self.problems {
+            if !valid_problem_id(problem) {
+                return Err(format!("invalid problem id: {problem}"));
+            }
+            if !problems.insert(problem) {
+                return Err(format!("problem {problem} is listed twice"));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// 順位の決め方
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ScoringRule {
+    /// 正解数の多い順、同じならペナルティ（正解までの時間と不正解の回数）の少ない順
+    Icpc,
+    /// 得点の高い順、同じなら最後に得点を更新した時刻とペナルティの和が小さい順
+    Atcoder,
+    /// 問題ごとの最高得点の和の高い順
+    Ioi,
+}
+
+/// 問題 ID として使える文字列かどうか。ディレクトリ名になるので、英数字と `-`, `_` に限る
+pub fn valid_problem_id(id: &str) -> bool {
+    !id.is_empty() && id.len() <= 64 && id.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_' )
+}
+
+/// タイムゾーンを含む TOML の日時を時刻に変換する
+fn system_time(datetime: &Datetime) -> Option<SystemTime> {
+    let (Some(date), Some(time), Some(offset)) = (datetime.date, datetime.time, datetime.offset) else { return None };
+    let offset_minutes = match offset {
+        Offset::Z => 0,
+        Offset::Custom { minutes } => minutes as i64,
+    };
+    // 1970-01-01 からの日数
+    let (month, day) = (date.month as i64, date.day as i64);
+    let year = date.year as i64 - if month <= 2 { 1 } else { 0 };
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let days = 365 * year + year / 4 - year / 100 + year / 400 + day_of_year - 719_468;
+    let seconds = days * 86400 + time.hour as i64 * 3600 + time.minute as i64 * 60 + time.second.unwrap_or(0) as i64 - offset_minutes * 60;
+    let seconds = u64::try_from(seconds).ok()?;
+    Some(UNIX_EPOCH + Duration::from_secs(seconds) + Duration::from_nanos(time.nanosecond.unwrap_or(0) as u64))
+}
+
+/// CORS の設定
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct CorsConfig {
+    /// 許可するオリジン（例: "https://contest.example.com"）。"*" ならすべてのオリジンを許可する
+    pub allowed_origins: Vec<String>,
+    /// 許可するメソッド。"*" ならすべてのメソッドを許可する
+    pub allowed_methods: Vec<String>,
+    /// 許可するリクエストヘッダ。"*" ならすべてのヘッダを許可する
+    pub allowed_headers: Vec<String>,
+    /// Cookie などの資格情報を含むリクエストを許可するかどうか
+    pub allow_credentials: bool,
+    /// プリフライトリクエストの結果をキャッシュしてよい秒数
+    pub max_age: Option<usize>,
+}
+
+impl CorsConfig {
+    fn validate(&self) -> Result<(), String> {
+        for origin in self.allowed_origins.iter().filter(|&origin| origin != "*" ) {
+            let uri = origin.parse::<Uri>().map_err(|err| format!("server.cors.allowed_origins: {origin}: {err}") )?;
+            if uri.scheme().is_none() || uri.host().is_none() {
+                return Err(format!("server.cors.allowed_origins: {origin} must be like https://example.com"));
+            }
+        }
+        for method in self.allowed_methods.iter().filter(|&method| method != "*" ) {
+            Method::from_bytes(method.as_bytes()).map_err(|err| format!("server.cors.allowed_methods: {method}: {err}") )?;
+        }
+        for header in self.allowed_headers.iter().filter(|&header| header != "*" ) {
+            HeaderName::from_bytes(header.as_bytes()).map_err(|err| format!("server.cors.allowed_headers: {header}: {err}") )?;
+        }
+        // 任意のオリジンからの資格情報付きのリクエストを許すと、他のサイトから利用者になりすませてしまう
+        if self.allow_credentials && self.allowed_origins.iter().any(|origin| origin == "*" ) {
+            return Err("server.cors: allow_credentials cannot be used with allowed_origins = [\"*\"]".to_string());
+        }
+        Ok(())
+    }
+}
+
+/// ログの出力の設定
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct LogConfig {
+    /// 出力するログのフィルタ。tracing-subscriber の EnvFilter の形式（例: "info", "judge=debug"）
+    pub level: String,
+    pub format: LogFormat,
+    /// 出力先のファイル。省略時は標準エラー出力
+    pub path: Option<String>,
+    /// 提出ごとに保持するログの最大件数
+    pub submission_entries: usize,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LogFormat {
+    Text,
+    /// 1 行に 1 つの JSON
+    Json,
+}
+
+/// テストで使う設定。`default.toml` に、重み 2 のクライアント `heavy` を加え、認証を有効にして API キーを加える
+#[cfg(test)]
+pub fn init_test_config() {
+    static INIT: std::sync::Once = std::sync::Once::new();
+    INIT.call_once(|| {
+        let path = Path::new(concat!(env!("CARGO_MANIFEST_DIR"), "/default.toml"));
+        let mut config = load(path).unwrap();
+        config.server.scheduler.client_weights.insert("heavy".to_string(), 2);
+        config.server.auth.enabled = true;
+        config.server.auth.keys = toml::from_str(r#"
+            alice = { key = "alice-key", scopes = ["submit", "status"], max_concurrent = 1, daily_run_ms = 100 }
+            bob = { key = "bob-key", scopes = ["status"], rate_limit = 2 }
+            root = { key = "root-key", scopes = ["admin"] }
+        "#).unwrap();
+        init(config, path);
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn datetime(text: &str) -> Datetime {
+        text.parse().unwrap()
+    }
+
+    fn unix_seconds(text: &str) -> Option<u64> {
+        system_time(&datetime(text)).map(|time| time.duration_since(UNIX_EPOCH).unwrap().as_secs() )
+    }
+
+    #[test]
+    fn system_time_converts_utc() {
+        assert_eq!(unix_seconds("1970-01-01T00:00:00Z"), Some(0));
+        assert_eq!(unix_seconds("2000-03-01T00:00:00Z"), Some(951_868_800));
+        assert_eq!(unix_seconds("2026-10-20T12:00:00Z"), Some(1_792_497_600));
+    }
+
+    #[test]
+    fn system_time_handles_leap_days() {
+        assert_eq!(unix_seconds("2024-02-29T00:00:00Z"), Some(1_709_164_800));
+        assert_eq!(unix_seconds("2024-03-01T00:00:00Z"), Some(1_709_164_800 + 86400));
+        // 2100 年はうるう年ではない
+        assert_eq!(unix_seconds("2100-03-01T00:00:00Z"), Some(unix_seconds("2100-02-28T00:00:00Z").unwrap() + 86400));
+    }
+
+    #[test]
+    fn system_time_applies_offset() {
+        assert_eq!(unix_seconds("2026-10-20T21:00:00+09:00"), unix_seconds("2026-10-20T12:00:00Z"));
+        assert_eq!(unix_seconds("2026-10-20T07:30:00-04:30"), unix_seconds("2026-10-20T12:00:00Z"));
+    }
+
+    #[test]
+    fn system_time_keeps_fractional_seconds() {
+        let time = system_time(&datetime("1970-01-01T00:00:01.5Z")).unwrap();
+        assert_eq!(time.duration_since(UNIX_EPOCH).unwrap(), Duration::from_millis(1500));
+    }
+
+    #[test]
+    fn system_time_rejects_local_and_pre_epoch_times() {
+        assert_eq!(unix_seconds("2026-10-20T21:00:00"), None);
+        assert_eq!(unix_seconds("2026-10-20"), None);
+        assert_eq!(unix_seconds("1969-12-31T23:59:59Z"), None);
+    }
+
+    #[test]
+    fn env_overrides_skip_unrelated_non_utf8_variables() {
+        use std::os::unix::ffi::OsStringExt;
+        let invalid = || OsString::from_vec(vec![b'a', 0xff]);
+        let var = |name: &str, value: OsString| (OsString::from(name), value);
+        let mut table = "[program]\ntime_limit = 2\n".parse::<Table>().unwrap();
+
+        let vars = vec![(invalid(), invalid()), var("PATH", invalid()), var("JUDGE_HOME", invalid()), var("JUDGE_PROGRAM__TIME_LIMIT", "5".into())];
+        apply_env_overrides(&mut table, vars.into_iter()).unwrap();
+        assert_eq!(table["program"]["time_limit"].as_integer(), Some(5));
+
+        let error = apply_env_overrides(&mut table, vec![var("JUDGE_PROGRAM__TIME_LIMIT", invalid())].into_iter()).unwrap_err();
+        assert!(error.contains("JUDGE_PROGRAM__TIME_LIMIT: the value is not valid UTF-8"), "{error}");
+        let mut name = b"JUDGE_PROGRAM__".to_vec();
+        name.push(0xff);
+        assert!(apply_env_overrides(&mut table, vec![(OsString::from_vec(name), "5".into())].into_iter()).is_err());
+    }
+
+    #[test]
+    fn env_overrides_keep_existing_key_case() {
+        let mut table = "[server.auth.keys.Alice]\nkey = \"a\"\n".parse::<Table>().unwrap();
+        apply_override(&mut table, "SERVER__AUTH__KEYS__Alice__KEY", "b").unwrap();
+        apply_override(&mut table, "PROGRAM__TIME_LIMIT", "5").unwrap();
+        assert_eq!(table["server"]["auth"]["keys"]["Alice"]["key"].as_str(), Some("b"));
+        assert_eq!(table["program"]["time_limit"].as_integer(), Some(5));
+    }
 }
@@ -0,0 +1,571 @@
+use std::collections::*;
+use std::error::Error;
+use std::fs::*;
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use tracing::error;
+
+use crate::config::*;
+use crate::problem::ProblemVerdict;
+use crate::program::Source;
+
+/// ジャッジが終わったコンテストへの提出
+static STORE: Lazy<Mutex<SubmissionStore>> = Lazy::new(|| Mutex::new(SubmissionStore::default()) );
+
+/// コンテストごとの、凍結を解除した (参加者, 問題) の組
+static REVEALED: Lazy<Mutex<HashMap<String, HashSet<Cell>>>> = Lazy::new(|| Mutex::new(HashMap::new()) );
+
+/// 順位表のマス: (参加者, 問題)
+type Cell = (String, String);
+
+/// 再ジャッジの ID から、再ジャッジの状況への対応
+static REJUDGES: Lazy<Mutex<HashMap<String, Rejudge>>> = Lazy::new(|| Mutex::new(HashMap::new()) );
+
+/// 再ジャッジする提出の ID と、そのために行った提出の ID の組
+pub type RejudgeTargets = Vec<(String, String)>;
+
+#[derive(Clone, Default)]
+struct Rejudge {
+    targets: RejudgeTargets,
+    /// 取り消されたか内部エラーになり、元の判定のままにした提出
+    failed: Vec<RejudgeFailure>,
+}
+
+/// 元の判定のままにした提出と、その理由
+#[derive(Clone, Debug, Serialize)]
+pub struct RejudgeFailure {
+    pub submission_id: String,
+    pub reason: String,
+}
+
+#[derive(Default)]
+struct SubmissionStore {
+    /// 記録を追記するファイル。起動時の `contest.submissions_path`
+    path: Option<String>,
+    /// 記録した順の判定。再ジャッジされた提出は複数回現れる
+    submissions: Vec<ContestSubmission>,
+}
+
+impl SubmissionStore {
+    /// 提出ごとの最新の判定
+    fn latest(&self) -> Vec<&ContestSubmission> {
+        let mut latest = HashMap::new();
+        for (i, submission) in self.submissions.iter().enumerate() {
+            latest.insert(&submission.submission_id, i);
+        }
+        let mut indices = latest.into_values().collect::<Vec<_>>();
+        indices.sort();
+        indices.into_iter().map(|i| &self.submissions[i] ).collect()
+    }
+
+    fn find(&self, submission_id: &str) -> Option<&ContestSubmission> {
+        self.submissions.iter().rev().find(|submission| submission.submission_id == submission_id )
+    }
+}
+
+/// コンテストへの提出の判定の記録
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct ContestSubmission {
+    pub submission_id: String,
+    pub contest: String,
+    pub problem: String,
+    pub participant: String,
+    /// 提出した時刻（UNIX 時間のミリ秒）
+    pub submitted_at: u64,
+    pub verdict: ProblemVerdict,
+    pub score: u64,
+    /// ジャッジに使ったテストデータの版
+    #[serde(default)]
+    pub test_data_version: u64,
+    /// 再ジャッジの結果のとき、その再ジャッジの ID
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rejudge_id: Option<String>,
+    /// 再ジャッジの結果のとき、再ジャッジ前の判定と得点
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub previous: Option<PreviousResult>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct PreviousResult {
+    pub verdict: ProblemVerdict,
+    pub score: u64,
+    #[serde(default)]
+    pub test_data_version: u64,
+}
+
+/// 再ジャッジのために保存する提出
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct StoredSource {
+    pub profile: String,
+    pub source: Source,
+}
+
+/// 再ジャッジする提出の条件。指定したすべての条件を満たす提出を再ジャッジする
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RejudgeFilter {
+    pub submission_ids: Option<Vec<String>>,
+    pub contest: Option<String>,
+    pub problem: Option<String>,
+    pub verdicts: Option<Vec<ProblemVerdict>>,
+    /// 提出した時刻の範囲（UNIX 時間のミリ秒）。`from` 以上 `to` 未満
+    pub from: Option<u64>,
+    pub to: Option<u64>,
+}
+
+impl RejudgeFilter {
+    pub fn is_empty(&self) -> bool {
+        self.submission_ids.is_none() && self.contest.is_none() && self.problem.is_none() && self.verdicts.is_none() && self.from.is_none() && self.to.is_none()
+    }
+
+    fn matches(&self, submission: &ContestSubmission) -> bool {
+        self.submission_ids.as_ref().is_none_or(|ids| ids.contains(&submission.submission_id) )
+            && self.contest.as_ref().is_none_or(|contest| contest == &submission.contest )
+            && self.problem.as_ref().is_none_or(|problem| problem == &submission.problem )
+            && self.verdicts.as_ref().is_none_or(|verdicts| verdicts.contains(&submission.verdict) )
+            && self.from.is_none_or(|from| from <= submission.submitted_at )
+            && self.to.is_none_or(|to| submission.submitted_at < to )
+    }
+}
+
+/// 再ジャッジの結果
+#[derive(Clone, Debug, Serialize)]
+pub struct RejudgeReport {
+    pub rejudge_id: String,
+    pub total: usize,
+    pub finished: usize,
+    /// まだ再ジャッジが終わっていない提出の ID
+    pub pending: Vec<String>,
+    /// 取り消されたか内部エラーになり、元の判定のままにした提出
+    pub failed: Vec<RejudgeFailure>,
+    /// 判定も得点も変わらなかった提出の数
+    pub unchanged: usize,
+    /// 判定か得点が変わった提出
+    pub changed: Vec<ContestSubmission>,
+}
+
+/// 順位表
+#[derive(Clone, Debug, Serialize)]
+pub struct Scoreboard {
+    pub contest: String,
+    pub scoring: ScoringRule,
+    pub problems: Vec<String>,
+    /// 凍結により結果を伏せた提出があるかどうか
+    pub frozen: bool,
+    pub rows: Vec<ScoreboardRow>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct ScoreboardRow {
+    /// 同順位の参加者は同じ値になる
+    pub rank: usize,
+    pub participant: String,
+    /// ICPC 形式では正解数、それ以外では得点の合計
+    pub score: u64,
+    /// 順位に使う時間（秒）。ICPC 形式では正解までの時間とペナルティの和、
+    /// AtCoder 形式では最後に得点を更新した時刻とペナルティの和
+    pub penalty: u64,
+    pub problems: BTreeMap<String, ProblemResult>,
+}
+
+/// 参加者の問題ごとの結果
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct ProblemResult {
+    pub score: u64,
+    pub accepted: bool,
+    /// 得点に数えた提出より前の不正解の数
+    pub wrong_attempts: u32,
+    /// 得点に数えた提出の、開始からの経過時間（秒）
+    pub time: Option<u64>,
+    /// 凍結により結果を伏せた提出の数
+    pub pending: u32,
+}
+
+/// `contest.submissions_path` から記録を読み込む。ファイルがなければ空から始める
+pub fn load() -> Result<(), Box<dyn Error>> {
+    let path = CONFIG.contest.submissions_path.clone();
+    let mut submissions = vec![];
+    match File::open(&path) {
+        Ok(file) => {
+            for (i, line) in BufReader::new(file).lines().enumerate() {
+                let line = line.map_err(|err| format!("{path}: {err}") )?;
+                if line.trim().is_empty() {
+                    continue;
+                }
+                submissions.push(serde_json::from_str::<ContestSubmission>(&line).map_err(|err| format!("{path}:{}: {err}", i + 1) )?);
+            }
+        }
+        Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+        Err(err) => return Err(format!("{path}: {err}").into()),
+    }
+    *STORE.lock().unwrap() = SubmissionStore { path: Some(path), submissions };
+    Ok(())
+}
+
+/// 提出の判定を記録する。ファイルへの書き込みに失敗しても、メモリ上には記録する
+///
+/// 再ジャッジの結果のときは、それまでの最新の判定を `previous` に設定する。
+pub fn record(mut submission: ContestSubmission) {
+    let mut store = STORE.lock().unwrap();
+    if submission.rejudge_id.is_some() {
+        submission.previous = store.find(&submission.submission_id).map(|previous| PreviousResult { verdict: previous.verdict, score: previous.score, test_data_version: previous.test_data_version } );
+    }
+    if let Some(path) = &store.path {
+        let line = serde_json::to_string(&submission).unwrap();
+        let result = OpenOptions::new().create(true).append(true).open(path).and_then(|mut file| writeln!(file, "{line}") );
+        if let Err(err) = result {
+            error!(%err, path, "failed to record the contest submission");
+        }
+    }
+    store.submissions.push(submission);
+}
+
+/// 記録した提出から順位表を計算する
+///
+/// 登録された参加者の、コンテストの期間内の提出だけを数える。
+/// `full` でなければ、凍結後の提出のうち凍結を解除していないものは結果を伏せて `pending` として数える。
+pub fn scoreboard(contest_id: &str, contest: &ContestConfig, full: bool) -> Scoreboard {
+    let start = unix_millis(contest.start());
+    let end = unix_millis(contest.end());
+    let freeze = contest.freeze().map(unix_millis).filter(|_| !full );
+    let mut submissions = STORE.lock().unwrap().latest().into_iter()
+        .filter(|submission| submission.contest == contest_id && start <= submission.submitted_at && submission.submitted_at < end )
+        .cloned()
+        .collect::<Vec<_>>();
+    submissions.sort_by_key(|submission| submission.submitted_at );
+
+    let revealed = REVEALED.lock().unwrap().get(contest_id).cloned().unwrap_or_default();
+    let is_hidden = |submission: &ContestSubmission| {
+        freeze.is_some_and(|freeze| submission.submitted_at >= freeze ) && !revealed.contains(&(submission.participant.clone(), submission.problem.clone()))
+    };
+
+    let mut rows = contest.participants.iter()
+        .map(|participant| {
+            let problems = contest.problems.iter()
+                .map(|problem| {
+                    let (hidden, visible) = submissions.iter()
+                        .filter(|submission| &submission.participant == participant && &submission.problem == problem )
+                        .partition::<Vec<_>, _>(|&submission| is_hidden(submission) );
+                    let mut result = problem_result(contest.scoring, visible.into_iter(), start);
+                    result.pending = hidden.len() as u32;
+                    (problem.clone(), result)
+                })
+                .collect::<BTreeMap<_, _>>();
+            let (score, penalty) = total(contest, problems.values());
+            ScoreboardRow { rank: 0, participant: participant.clone(), score, penalty, problems }
+        })
+        .collect::<Vec<_>>();
+
+    rows.sort_by(|a, b| b.score.cmp(&a.score).then(a.penalty.cmp(&b.penalty)).then(a.participant.cmp(&b.participant)) );
+    for i in 0 .. rows.len() {
+        rows[i].rank = if i > 0 && (rows[i - 1].score, rows[i - 1].penalty) == (rows[i].score, rows[i].penalty) { rows[i - 1].rank } else { i + 1 };
+    }
+
+    let frozen = rows.iter().any(|row| row.problems.values().any(|result| result.pending > 0 ) );
+    Scoreboard { contest: contest_id.to_string(), scoring: contest.scoring, problems: contest.problems.clone(), frozen, rows }
+}
+
+/// 条件に合う提出の最新の判定
+pub fn find_submissions(filter: &RejudgeFilter) -> Vec<ContestSubmission> {
+    STORE.lock().unwrap().latest().into_iter().filter(|submission| filter.matches(submission) ).cloned().collect()
+}
+
+/// 再ジャッジを登録する
+pub fn start_rejudge(rejudge_id: &str, targets: RejudgeTargets) {
+    REJUDGES.lock().unwrap().insert(rejudge_id.to_string(), Rejudge { targets, failed: vec![] });
+}
+
+/// 再ジャッジが取り消されたか内部エラーになった提出を記録する。判定は記録せず、元の判定のままにする
+pub fn fail_rejudge(rejudge_id: &str, submission_id: &str, reason: &str) {
+    if let Some(rejudge) = REJUDGES.lock().unwrap().get_mut(rejudge_id) {
+        rejudge.failed.push(RejudgeFailure { submission_id: submission_id.to_string(), reason: reason.to_string() });
+    }
+}
+
+/// 再ジャッジの前後で判定が変わった提出を集める
+///
+/// 再起動後は、記録された判定から終わった再ジャッジの結果だけを返す。
+pub fn rejudge_report(rejudge_id: &str) -> Option<RejudgeReport> {
+    let Rejudge { targets, failed } = REJUDGES.lock().unwrap().get(rejudge_id).cloned().unwrap_or_default();
+    let store = STORE.lock().unwrap();
+    let results = store.submissions.iter().filter(|submission| submission.rejudge_id.as_deref() == Some(rejudge_id) ).collect::<Vec<_>>();
+    if targets.is_empty() && results.is_empty() {
+        return None;
+    }
+
+    let pending = targets.into_iter()
+        .map(|(submission_id, _)| submission_id )
+        .filter(|submission_id| !results.iter().any(|result| &result.submission_id == submission_id ) )
+        .filter(|submission_id| !failed.iter().any(|failure| &failure.submission_id == submission_id ) )
+        .collect::<Vec<_>>();
+    let changed = results.iter()
+        .filter(|result| result.previous.is_none_or(|previous| (previous.verdict, previous.score) != (result.verdict, result.score) ) )
+        .map(|&result| result.clone() )
+        .collect::<Vec<_>>();
+    Some(RejudgeReport {
+        rejudge_id: rejudge_id.to_string(),
+        total: results.len() + failed.len() + pending.len(),
+        finished: results.len(),
+        pending,
+        failed,
+        unchanged: results.len() - changed.len(),
+        changed,
+    })
+}
+
+/// 再ジャッジのために提出を保存する
+pub fn save_source(submission_id: &str, source: &StoredSource) -> io::Result<()> {
+    create_dir_all(&CONFIG.contest.sources_dir)?;
+    write(source_path(submission_id), serde_json::to_vec(source)?)
+}
+
+pub fn load_source(submission_id: &str) -> io::Result<StoredSource> {
+    let source = read(source_path(submission_id))?;
+    Ok(serde_json::from_slice(&source)?)
+}
+
+fn source_path(submission_id: &str) -> PathBuf {
+    PathBuf::from(&CONFIG.contest.sources_dir).join(format!("{submission_id}.json"))
+}
+
+/// 凍結により伏せた結果を 1 つ公開し、公開した (参加者, 問題) を返す
+///
+/// ICPC の表彰式と同じく、伏せた結果を持つ参加者のうち最も順位の低い参加者の、最初の問題を公開する。
+pub fn reveal_next(contest_id: &str, contest: &ContestConfig) -> Option<Cell> {
+    let scoreboard = scoreboard(contest_id, contest, false);
+    let (participant, problem) = scoreboard.rows.iter().rev().find_map(|row| {
+        let problem = contest.problems.iter().find(|&problem| row.problems[problem].pending > 0 )?;
+        Some((row.participant.clone(), problem.clone()))
+    })?;
+    REVEALED.lock().unwrap().entry(contest_id.to_string()).or_default().insert((participant.clone(), problem.clone()));
+    Some((participant, problem))
+}
+
+/// 凍結により伏せた結果をすべて公開する
+pub fn reveal_all(contest_id: &str, contest: &ContestConfig) {
+    let mut revealed = REVEALED.lock().unwrap();
+    let revealed = revealed.entry(contest_id.to_string()).or_default();
+    for participant in &contest.participants {
+        for problem in &contest.problems {
+            revealed.insert((participant.clone(), problem.clone()));
+        }
+    }
+}
+
+/// 1 人の参加者の 1 問への提出（提出順）から結果を求める
+fn problem_result<'a>(scoring: ScoringRule, submissions: impl Iterator<Item = &'a ContestSubmission>, start: u64) -> ProblemResult {
+    let mut result = ProblemResult::default();
+    let mut wrong_attempts = 0;
+    for submission in submissions {
+        let time = (submission.submitted_at - start) / 1000;
+        match scoring {
+            // 最初の正解までを数える
+            ScoringRule::Icpc => {
+                if submission.verdict == ProblemVerdict::Accepted {
+                    result = ProblemResult { score: submission.score, accepted: true, wrong_attempts, time: Some(time), pending: 0 };
+                    break;
+                }
+            }
+            // 得点を更新した最後の提出までを数える
+            ScoringRule::Atcoder | ScoringRule::Ioi => {
+                if submission.score > result.score {
+                    result = ProblemResult { score: submission.score, accepted: result.accepted, wrong_attempts, time: Some(time), pending: 0 };
+                }
+                result.accepted |= submission.verdict == ProblemVerdict::Accepted;
+            }
+        }
+        if submission.verdict.is_rejected() {
+            wrong_attempts += 1;
+        }
+    }
+    if result.time.is_none() {
+        result.wrong_attempts = wrong_attempts;
+    }
+    result
+}
+
+/// 得点と、順位に使う時間
+fn total<'a>(contest: &ContestConfig, results: impl Iterator<Item = &'a ProblemResult> + Clone) -> (u64, u64) {
+    let penalty_seconds = contest.penalty_minutes * 60;
+    match contest.scoring {
+        ScoringRule::Icpc => {
+            let solved = results.clone().filter(|result| result.accepted );
+            let penalty = solved.clone().map(|result| result.time.unwrap_or(0) + result.wrong_attempts as u64 * penalty_seconds ).sum();
+            (solved.count() as u64, penalty)
+        }
+        ScoringRule::Atcoder => {
+            let scored = results.filter(|result| result.score > 0 );
+            let score = scored.clone().map(|result| result.score ).sum();
+            let last = scored.clone().filter_map(|result| result.time ).max().unwrap_or(0);
+            let wrong_attempts = scored.map(|result| result.wrong_attempts as u64 ).sum::<u64>();
+            (score, last + wrong_attempts * penalty_seconds)
+        }
+        ScoringRule::Ioi => (results.map(|result| result.score ).sum(), 0),
+    }
+}
+
+pub fn unix_millis(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH).unwrap_or_default().as_millis() as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 12:00 から 14:00 (UTC) まで、問題 A と B、参加者 alice, bob, carol
+    fn contest(scoring: ScoringRule, freeze_minutes: Option<u64>) -> ContestConfig {
+        ContestConfig {
+            title: "test".to_string(),
+            start_time: "2026-10-20T12:00:00Z".parse().unwrap(),
+            end_time: "2026-10-20T14:00:00Z".parse().unwrap(),
+            problems: vec!["A".to_string(), "B".to_string()],
+            participants: vec!["alice".to_string(), "bob".to_string(), "carol".to_string()],
+            scoring,
+            penalty_minutes: 20,
+            freeze_minutes,
+        }
+    }
+
+    /// 開始から `minutes` 分後の提出を記録し、提出の ID を返す
+    fn add(contest_id: &str, contest: &ContestConfig, participant: &str, problem: &str, minutes: i64, verdict: ProblemVerdict, score: u64) -> String {
+        let mut store = STORE.lock().unwrap();
+        let submission_id = format!("{contest_id}-{}", store.submissions.len());
+        store.submissions.push(ContestSubmission {
+            submission_id: submission_id.clone(),
+            contest: contest_id.to_string(),
+            problem: problem.to_string(),
+            participant: participant.to_string(),
+            submitted_at: (unix_millis(contest.start()) as i64 + minutes * 60_000) as u64,
+            verdict,
+            score,
+            test_data_version: 1,
+            rejudge_id: None,
+            previous: None,
+        });
+        submission_id
+    }
+
+    fn row<'a>(scoreboard: &'a Scoreboard, participant: &str) -> &'a ScoreboardRow {
+        scoreboard.rows.iter().find(|row| row.participant == participant ).unwrap()
+    }
+
+    fn ranking(scoreboard: &Scoreboard) -> Vec<(usize, &str, u64, u64)> {
+        scoreboard.rows.iter().map(|row| (row.rank, row.participant.as_str(), row.score, row.penalty) ).collect()
+    }
+
+    use ProblemVerdict::*;
+
+    #[test]
+    fn icpc_counts_wrong_attempts_before_the_first_accepted() {
+        let (id, contest) = ("test-icpc", contest(ScoringRule::Icpc, None));
+        add(id, &contest, "alice", "A", -5, Accepted, 100);
+        add(id, &contest, "alice", "A", 10, WrongAnswer, 0);
+        add(id, &contest, "alice", "A", 12, CompileError, 0);
+        add(id, &contest, "alice", "A", 20, Accepted, 100);
+        add(id, &contest, "alice", "A", 25, WrongAnswer, 0);
+        add(id, &contest, "alice", "B", 30, WrongAnswer, 0);
+        add(id, &contest, "bob", "A", 5, Accepted, 100);
+        add(id, &contest, "bob", "B", 50, RuntimeError, 0);
+        add(id, &contest, "bob", "B", 60, InternalError, 0);
+        add(id, &contest, "bob", "B", 100, Accepted, 100);
+        add(id, &contest, "carol", "A", 121, Accepted, 100);
+
+        let scoreboard = scoreboard(id, &contest, false);
+        assert_eq!(ranking(&scoreboard), [(1, "bob", 2, 300 + 6000 + 1200), (2, "alice", 1, 1200 + 1200), (3, "carol", 0, 0)]);
+        let alice = row(&scoreboard, "alice");
+        assert_eq!((alice.problems["A"].wrong_attempts, alice.problems["A"].time), (1, Some(1200)));
+        assert_eq!((alice.problems["B"].accepted, alice.problems["B"].wrong_attempts, alice.problems["B"].time), (false, 1, None));
+        assert!(!scoreboard.frozen);
+    }
+
+    #[test]
+    fn atcoder_uses_the_last_score_update_and_penalizes_only_scored_problems() {
+        let (id, contest) = ("test-atcoder", contest(ScoringRule::Atcoder, None));
+        add(id, &contest, "alice", "A", 10, WrongAnswer, 50);
+        add(id, &contest, "alice", "A", 15, WrongAnswer, 0);
+        add(id, &contest, "alice", "A", 30, Accepted, 100);
+        add(id, &contest, "alice", "B", 40, WrongAnswer, 0);
+        add(id, &contest, "bob", "A", 50, Accepted, 100);
+        add(id, &contest, "carol", "A", 10, TimeLimitExceeded, 0);
+        add(id, &contest, "carol", "A", 20, Accepted, 100);
+
+        let scoreboard = scoreboard(id, &contest, false);
+        assert_eq!(ranking(&scoreboard), [(1, "carol", 100, 1200 + 1200), (2, "bob", 100, 3000), (3, "alice", 100, 1800 + 2 * 1200)]);
+        assert_eq!(row(&scoreboard, "alice").problems["B"].wrong_attempts, 1);
+    }
+
+    #[test]
+    fn ioi_sums_the_best_scores_and_ties_share_a_rank() {
+        let (id, contest) = ("test-ioi", contest(ScoringRule::Ioi, None));
+        add(id, &contest, "alice", "A", 10, WrongAnswer, 60);
+        add(id, &contest, "alice", "A", 20, WrongAnswer, 40);
+        add(id, &contest, "alice", "B", 30, WrongAnswer, 40);
+        add(id, &contest, "bob", "A", 50, Accepted, 100);
+
+        let scoreboard = scoreboard(id, &contest, false);
+        assert_eq!(ranking(&scoreboard), [(1, "alice", 100, 0), (1, "bob", 100, 0), (3, "carol", 0, 0)]);
+    }
+
+    #[test]
+    fn rejudged_results_replace_earlier_ones() {
+        let (id, contest) = ("test-rejudge", contest(ScoringRule::Icpc, None));
+        let submission_id = add(id, &contest, "alice", "A", 10, WrongAnswer, 0);
+        let mut rejudged = STORE.lock().unwrap().find(&submission_id).unwrap().clone();
+        rejudged.verdict = Accepted;
+        rejudged.rejudge_id = Some("rejudge".to_string());
+        STORE.lock().unwrap().submissions.push(rejudged);
+
+        let scoreboard = scoreboard(id, &contest, false);
+        assert_eq!(ranking(&scoreboard)[0], (1, "alice", 1, 600));
+    }
+
+    #[test]
+    fn failed_rejudges_keep_the_previous_result() {
+        let (id, contest) = ("test-failed-rejudge", contest(ScoringRule::Icpc, None));
+        let accepted = add(id, &contest, "alice", "A", 10, Accepted, 100);
+        let wrong = add(id, &contest, "bob", "A", 20, WrongAnswer, 0);
+        start_rejudge("failed-rejudge", vec![(accepted.clone(), "job-1".to_string()), (wrong.clone(), "job-2".to_string())]);
+        fail_rejudge("failed-rejudge", &accepted, "internal error");
+
+        let report = rejudge_report("failed-rejudge").unwrap();
+        assert_eq!((report.total, report.finished, report.pending), (2, 0, vec![wrong]));
+        assert_eq!(report.failed.iter().map(|failure| (failure.submission_id.as_str(), failure.reason.as_str()) ).collect::<Vec<_>>(), [(accepted.as_str(), "internal error")]);
+        assert_eq!(ranking(&scoreboard(id, &contest, false))[0], (1, "alice", 1, 600));
+    }
+
+    #[test]
+    fn freeze_hides_late_results_until_revealed() {
+        let (id, contest) = ("test-freeze", contest(ScoringRule::Icpc, Some(30)));
+        add(id, &contest, "alice", "A", 80, Accepted, 100);
+        add(id, &contest, "bob", "A", 85, WrongAnswer, 0);
+        add(id, &contest, "bob", "A", 90, Accepted, 100);
+        add(id, &contest, "alice", "B", 95, Accepted, 100);
+        add(id, &contest, "carol", "B", 100, WrongAnswer, 0);
+
+        let frozen = scoreboard(id, &contest, false);
+        assert!(frozen.frozen);
+        assert_eq!(ranking(&frozen), [(1, "alice", 1, 4800), (2, "bob", 0, 0), (2, "carol", 0, 0)]);
+        assert_eq!(row(&frozen, "bob").problems["A"].wrong_attempts, 1);
+        assert_eq!(row(&frozen, "bob").problems["A"].pending, 1);
+        assert_eq!(row(&frozen, "alice").problems["B"].pending, 1);
+
+        let full = scoreboard(id, &contest, true);
+        assert!(!full.frozen);
+        assert_eq!(ranking(&full), [(1, "alice", 2, 4800 + 5700), (2, "bob", 1, 5400 + 1200), (3, "carol", 0, 0)]);
+
+        // 最も順位の低い参加者から公開する
+        let cell = |participant: &str, problem: &str| Some((participant.to_string(), problem.to_string()));
+        assert_eq!(reveal_next(id, &contest), cell("carol", "B"));
+        assert_eq!(reveal_next(id, &contest), cell("bob", "A"));
+        assert_eq!(reveal_next(id, &contest), cell("alice", "B"));
+        assert_eq!(reveal_next(id, &contest), None);
+        let revealed = scoreboard(id, &contest, false);
+        assert!(!revealed.frozen);
+        assert_eq!(ranking(&revealed), ranking(&full));
+    }
+}
@@ -0,0 +1,93 @@
+use std::fmt;
+
+use actix_web::http::{header, StatusCode};
+use actix_web::{HttpResponse, ResponseError};
+use serde::Serialize;
+
+/// 機械可読なエラーの種類
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorCode {
+    InvalidRequest,
+    InvalidId,
+    NotFound,
+    PayloadTooLarge,
+    SourceTooLarge,
+    TooManyInputs,
+    InputTooLarge,
+    UnsupportedLanguage,
+    UnknownProfile,
+    InvalidArchive,
+    AlreadyFinished,
+    Unauthorized,
+    Forbidden,
+    RateLimited,
+    QuotaExceeded,
+    InvalidConfig,
+    NotParticipant,
+    ContestNotRunning,
+    ContestNotFinished,
+    InvalidTestData,
+    PrepareRunning,
+    InternalError,
+}
+
+/// API のエラーレスポンス
+///
+/// `{"code": "source_too_large", "message": "..."}` の形の JSON として返す。
+#[derive(Clone, Debug, Serialize)]
+pub struct ApiError {
+    pub code: ErrorCode,
+    pub message: String,
+    /// 再試行できるようになるまでの秒数。`Retry-After` ヘッダーにも設定する
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub retry_after: Option<u64>,
+    /// エラーの詳細。検証器がテストケースを受け付けなかったときの位置など
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub details: Option<serde_json::Value>,
+}
+
+impl ApiError {
+    pub fn new(code: ErrorCode, message: impl Into<String>) -> Self {
+        Self { code, message: message.into(), retry_after: None, details: None }
+    }
+
+    pub fn with_retry_after(mut self, seconds: u64) -> Self {
+        self.retry_after = Some(seconds);
+        self
+    }
+
+    pub fn with_details(mut self, details: impl Serialize) -> Self {
+        self.details = serde_json::to_value(details).ok();
+        self
+    }
+}
+
+impl fmt::Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl ResponseError for ApiError {
+    fn status_code(&self) -> StatusCode {
+        match self.code {
+            ErrorCode::NotFound => StatusCode::NOT_FOUND,
+            ErrorCode::PayloadTooLarge => StatusCode::PAYLOAD_TOO_LARGE,
+            ErrorCode::AlreadyFinished | ErrorCode::ContestNotFinished | ErrorCode::PrepareRunning => StatusCode::CONFLICT,
+            ErrorCode::Unauthorized => StatusCode::UNAUTHORIZED,
+            ErrorCode::Forbidden | ErrorCode::NotParticipant | ErrorCode::ContestNotRunning => StatusCode::FORBIDDEN,
+            ErrorCode::RateLimited | ErrorCode::QuotaExceeded => StatusCode::TOO_MANY_REQUESTS,
+            ErrorCode::InvalidConfig | ErrorCode::InternalError => StatusCode::INTERNAL_SERVER_ERROR,
+            _ => StatusCode::BAD_REQUEST,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let mut response = HttpResponse::build(self.status_code());
+        if let Some(retry_after) = self.retry_after {
+            response.insert_header((header::RETRY_AFTER, retry_after));
+        }
+        response.json(self)
+    }
+}
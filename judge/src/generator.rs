@@ -0,0 +1,229 @@
+use std::collections::*;
+use std::error::Error;
+use std::fs::*;
+use std::path::{Path, PathBuf};
+
+use ring::digest::{Context, SHA256};
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+use uuid::Uuid;
+
+use crate::config::*;
+use crate::metrics::{label, METRICS};
+use crate::problem::{self, problem_dir, problem_file_path, ProblemManifest, TestDataError};
+use crate::program::compile::find_profile;
+use crate::program::execute::{KillHandle, Verdict};
+use crate::program::{Program, Source, SourceError};
+
+/// `problem.toml` の `[[generators]]`
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct GeneratorConfig {
+    /// スクリプトで使う名前
+    pub name: String,
+    /// 生成器のソースコード。`PUT /admin/problems/{problem_id}/files/{name}` で置いたファイルの名前
+    pub source: String,
+}
+
+/// スクリプトの 1 行。`<生成器の名前> <引数>... > <テストケースの名前>`
+#[derive(Clone, Debug)]
+struct ScriptLine {
+    generator: String,
+    args: Vec<String>,
+    test: String,
+}
+
+/// 生成した入力と、キャッシュから取り出した入力の数
+pub struct Generated {
+    pub inputs: Vec<(String, String)>,
+    pub cached: usize,
+}
+
+/// `problem.toml` の生成器とスクリプトの指定を確かめる
+pub fn validate_generators(manifest: &ProblemManifest) -> Result<(), TestDataError> {
+    let mut names = HashSet::new();
+    for generator in &manifest.generators {
+        if !valid_problem_id(&generator.name) || !names.insert(&generator.name) {
+            return Err(TestDataError(format!("invalid or duplicate generator name: {}", generator.name)));
+        }
+        if !problem::valid_file_name(&generator.source) {
+            return Err(TestDataError(format!("invalid file name: {}", generator.source)));
+        }
+    }
+    match &manifest.script {
+        Some(script) if !problem::valid_file_name(script) => Err(TestDataError(format!("invalid file name: {script}"))),
+        Some(_) if !manifest.generates_outputs() => Err(TestDataError("a script requires a main solution to generate the outputs".to_string())),
+        None if !manifest.generators.is_empty() => Err(TestDataError("generators require a script".to_string())),
+        _ => Ok(()),
+    }
+}
+
+/// スクリプトに従って生成器を実行し、テストケースの入力を作る
+///
+/// 入力は生成器のソースコード、コンパイルプロファイルと引数ごとに `problems/<問題 ID>/cache/` にキャッシュし、生成器が変わったときだけ生成し直す。
+/// 生成器は決定的でなければならない。乱数の種は引数で渡す。
+pub fn generate(problem_id: &str, manifest: &ProblemManifest) -> Result<Generated, Box<dyn Error>> {
+    let Some(script) = &manifest.script else { return Ok(Generated { inputs: vec![], cached: 0 }) };
+    let script = read_to_string(problem_file_path(problem_id, script)?).map_err(|err| TestDataError(format!("failed to read the script {script}: {err}")) )?;
+    let lines = parse_script(&script, manifest)?;
+
+    let mut sources = HashMap::new();
+    for generator in &manifest.generators {
+        let source = read_to_string(problem_file_path(problem_id, &generator.source)?)
+            .map_err(|err| TestDataError(format!("failed to read generator {}: {err}", generator.source)) )?;
+        sources.insert(generator.name.clone(), source);
+    }
+
+    let profile = find_profile(&CONFIG.program.default_profile)?;
+    let profile_json = serde_json::to_string(profile)?;
+    let cache_dir = problem_dir(problem_id).join("cache");
+    create_dir_all(&cache_dir)?;
+    let mut programs = HashMap::new();
+    let mut used = HashSet::new();
+    let mut inputs = vec![];
+    let mut cached = 0;
+    for (i, line) in lines.iter().enumerate() {
+        let source = &sources[&line.generator];
+        let cache_path = cache_path(&cache_dir, source, &profile_json, &line.args);
+        used.insert(cache_path.clone());
+        let input = read_to_string(&cache_path);
+        METRICS.cache_lookup("generator", input.is_ok());
+        if let Ok(input) = input {
+            cached += 1;
+            inputs.push((line.test.clone(), input));
+            continue;
+        }
+
+        if !programs.contains_key(&line.generator) {
+            programs.insert(line.generator.clone(), compile(&line.generator, source, profile)?);
+        }
+        let result = programs[&line.generator].run_with_args("", &line.args, &KillHandle::default())?;
+        if result.verdict != Verdict::Ok {
+            let stderr = result.stderr.lines().next().unwrap_or_default();
+            return Err(TestDataError(format!("generator {} failed on script line {} ({}): {stderr}", line.generator, i + 1, label(result.verdict))).into());
+        }
+        if result.stdout.len() > CONFIG.problem.max_test_bytes {
+            return Err(TestDataError(format!("{}.in must be at most {} bytes, but the generator printed {} bytes", line.test, CONFIG.problem.max_test_bytes, result.stdout.len())).into());
+        }
+        problem::write_atomically(&cache_path, result.stdout.as_bytes())?;
+        inputs.push((line.test.clone(), result.stdout));
+    }
+
+    // 使わなくなったキャッシュを消す
+    for entry in read_dir(&cache_dir)? {
+        let path = entry?.path();
+        if !used.contains(&path) {
+            if let Err(err) = remove_file(&path) {
+                warn!(%err, path = %path.display(), "failed to remove an unused cache file");
+            }
+        }
+    }
+    info!(generated = inputs.len() - cached, cached, "generated test inputs");
+    Ok(Generated { inputs, cached })
+}
+
+/// 空行と `#` で始まる行は読み飛ばす。テストケースの名前を省略すると、行の順に `gen_01`, `gen_02`, ... とする
+fn parse_script(script: &str, manifest: &ProblemManifest) -> Result<Vec<ScriptLine>, TestDataError> {
+    let mut lines = vec![];
+    let mut tests = HashSet::new();
+    for (i, line) in script.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let (command, test) = match line.rsplit_once('>') {
+            Some((command, test)) => (command, test.trim().to_string()),
+            None => (line, format!("gen_{:02}", lines.len() + 1)),
+        };
+        let mut words = command.split_whitespace().map(str::to_string);
+        let generator = words.next().ok_or_else(|| TestDataError(format!("script line {}: missing generator", i + 1)) )?;
+        if !manifest.generators.iter().any(|config| config.name == generator ) {
+            return Err(TestDataError(format!("script line {}: unknown generator {generator}", i + 1)));
+        }
+        if !valid_problem_id(&test) || !tests.insert(test.clone()) {
+            return Err(TestDataError(format!("script line {}: invalid or duplicate test case name {test}", i + 1)));
+        }
+        lines.push(ScriptLine { generator, args: words.collect(), test });
+    }
+    Ok(lines)
+}
+
+/// 生成器のソースコード、プロファイルの内容と引数のハッシュをキャッシュのファイル名とする
+///
+/// プロファイルは名前ではなく JSON にした内容を使い、設定でオプションやツールチェインを変えたときも生成し直す。
+fn cache_path(cache_dir: &Path, source: &str, profile_json: &str, args: &[String]) -> PathBuf {
+    let mut context = Context::new(&SHA256);
+    for part in [source, profile_json].into_iter().chain(args.iter().map(String::as_str)) {
+        context.update(&(part.len() as u64).to_le_bytes());
+        context.update(part.as_bytes());
+    }
+    let hash = context.finish().as_ref().iter().map(|byte| format!("{byte:02x}") ).collect::<String>();
+    cache_dir.join(format!("{hash}.in"))
+}
+
+fn compile(name: &str, source: &str, profile: &CompileProfile) -> Result<Program, Box<dyn Error>> {
+    let program = Program::new(&format!("generator-{}", Uuid::new_v4().hyphenated()), &Source::Single(source.to_string()), profile)
+        .map_err(|err| if err.is::<SourceError>() { TestDataError(format!("invalid generator {name}: {err}")).into() } else { err } )?;
+    if !program.compile_result().exit.success() {
+        return Err(TestDataError(format!("failed to compile generator {name}:\n{}", program.compile_result().error_message())).into());
+    }
+    Ok(program)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn manifest() -> ProblemManifest {
+        toml::from_str(r#"
+            title = "A"
+            script = "script.txt"
+            generators = [{ name = "random", source = "random.rs" }, { name = "line", source = "line.rs" }]
+            solutions = [{ source = "main.rs", verdict = "AC", main = true }]
+        "#).unwrap()
+    }
+
+    fn parse(script: &str) -> Result<Vec<(String, Vec<String>, String)>, String> {
+        parse_script(script, &manifest())
+            .map(|lines| lines.into_iter().map(|line| (line.generator, line.args, line.test) ).collect() )
+            .map_err(|err| err.0 )
+    }
+
+    fn line(generator: &str, args: &[&str], test: &str) -> (String, Vec<String>, String) {
+        (generator.to_string(), args.iter().map(|arg| arg.to_string() ).collect(), test.to_string())
+    }
+
+    #[test]
+    fn script_skips_comments_and_names_tests_in_order() {
+        let script = "# small cases\n\nrandom 10 1\n  random 10 2 > small_2\n\t# large cases\nline 100000\n";
+        assert_eq!(parse(script).unwrap(), [
+            line("random", &["10", "1"], "gen_01"),
+            line("random", &["10", "2"], "small_2"),
+            line("line", &["100000"], "gen_03"),
+        ]);
+    }
+
+    #[test]
+    fn script_errors_report_the_line_number() {
+        assert_eq!(parse("random 1\n\nunknown 2\n").unwrap_err(), "script line 3: unknown generator unknown");
+        assert_eq!(parse("# a\n> a\n").unwrap_err(), "script line 2: missing generator");
+        assert_eq!(parse("random 1 > a\nrandom 2 > a\n").unwrap_err(), "script line 2: invalid or duplicate test case name a");
+        assert_eq!(parse("random 1 > ../a\n").unwrap_err(), "script line 1: invalid or duplicate test case name ../a");
+        assert_eq!(parse("random 1 > gen_02\nrandom 2\n").unwrap_err(), "script line 2: invalid or duplicate test case name gen_02");
+    }
+
+    #[test]
+    fn cache_path_depends_on_source_profile_and_args() {
+        let dir = Path::new("cache");
+        let args = |args: &[&str]| args.iter().map(|arg| arg.to_string() ).collect::<Vec<_>>();
+        let base = cache_path(dir, "fn main() {}", "{}", &args(&["1", "2"]));
+        assert_eq!(base, cache_path(dir, "fn main() {}", "{}", &args(&["1", "2"])));
+        assert_eq!(base.parent(), Some(dir));
+        assert_eq!(base.extension().unwrap(), "in");
+        assert_ne!(base, cache_path(dir, "fn main() { }", "{}", &args(&["1", "2"])));
+        assert_ne!(base, cache_path(dir, "fn main() {}", r#"{"opt":3}"#, &args(&["1", "2"])));
+        assert_ne!(base, cache_path(dir, "fn main() {}", "{}", &args(&["2", "1"])));
+        assert_ne!(base, cache_path(dir, "fn main() {}", "{}", &args(&["12"])));
+        assert_ne!(base, cache_path(dir, "fn main() {}", "{}", &args(&["1", "2", ""])));
+    }
+}
@@ -0,0 +1,126 @@
+use std::error::Error;
+use std::fs::*;
+use std::os::unix::fs::MetadataExt;
+use std::path::Path;
+use std::process::*;
+use std::sync::Mutex;
+
+use once_cell::sync::Lazy;
+use serde::Serialize;
+use uuid::Uuid;
+
+use crate::config::*;
+use crate::program::compile::find_profile;
+use crate::program::execute::{KillHandle, Verdict, CGROUP_TASKS_PATHS};
+use crate::program::{Program, Source};
+
+/// 最後に行った自己診断の結果
+static LAST_REPORT: Lazy<Mutex<Option<HealthReport>>> = Lazy::new(|| Mutex::new(None) );
+
+const SELF_TEST_SOURCE: &str = r#"fn main() {
+    let mut line = String::new();
+    std::io::stdin().read_line(&mut line).unwrap();
+    let sum: i64 = line.split_whitespace().map(|x| x.parse::<i64>().unwrap() ).sum();
+    println!("{sum}");
+}
+"#;
+
+/// 自己診断の結果
+#[derive(Clone, Debug, Serialize)]
+pub struct HealthReport {
+    /// すべての項目に成功したかどうか
+    pub ready: bool,
+    pub checks: Vec<HealthCheck>,
+}
+
+/// 自己診断の 1 項目の結果
+#[derive(Clone, Debug, Serialize)]
+pub struct HealthCheck {
+    pub name: &'static str,
+    pub ok: bool,
+    pub message: String,
+}
+
+/// 最後の自己診断の結果を返す。まだ行っていなければ `None`
+pub fn last_report() -> Option<HealthReport> {
+    LAST_REPORT.lock().unwrap().clone()
+}
+
+/// ジャッジに必要な環境を項目ごとに確認し、実際に簡単なプログラムをコンパイル・実行する
+pub fn self_test() -> HealthReport {
+    let checks = vec![
+        run_check("rustc", check_rustc),
+        run_check("safe_run", check_safe_run),
+        run_check("cgroup", check_cgroup),
+        run_check("lib_mounts", check_lib_mounts),
+        run_check("program", check_program),
+    ];
+    let report = HealthReport { ready: checks.iter().all(|check| check.ok ), checks };
+    *LAST_REPORT.lock().unwrap() = Some(report.clone());
+    report
+}
+
+fn run_check(name: &'static str, check: impl FnOnce() -> Result<String, Box<dyn Error>>) -> HealthCheck {
+    match check() {
+        Ok(message) => HealthCheck { name, ok: true, message },
+        Err(err) => HealthCheck { name, ok: false, message: err.to_string() },
+    }
+}
+
+fn check_rustc() -> Result<String, Box<dyn Error>> {
+    let profile = find_profile(&CONFIG.program.default_profile)?;
+    let output = Command::new("rustc")
+        .args(profile.toolchain.as_ref().map(|toolchain| format!("+{toolchain}") ))
+        .arg("--version")
+        .output()
+        .map_err(|err| format!("failed to run rustc: {err}") )?;
+    if !output.status.success() {
+        return Err(format!("rustc --version failed: {}", String::from_utf8_lossy(&output.stderr).trim()).into());
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+/// safe_run は root が所有し、setuid ビットが立っている必要がある
+fn check_safe_run() -> Result<String, Box<dyn Error>> {
+    let metadata = metadata("safe_run").map_err(|err| format!("./safe_run: {err}") )?;
+    if metadata.uid() != 0 {
+        return Err(format!("./safe_run must be owned by root, but owned by uid {}", metadata.uid()).into());
+    }
+    if metadata.mode() & 0o4000 == 0 {
+        return Err(format!("./safe_run must have the setuid bit, but its mode is {:o}", metadata.mode() & 0o7777).into());
+    }
+    Ok("./safe_run is setuid root".to_string())
+}
+
+fn check_cgroup() -> Result<String, Box<dyn Error>> {
+    for cgroup_task_path in &CGROUP_TASKS_PATHS {
+        OpenOptions::new().append(true).open(cgroup_task_path).map_err(|err| format!("{cgroup_task_path} is not writable: {err}") )?;
+    }
+    Ok(format!("{CGROUP_TASKS_PATHS:?} are writable"))
+}
+
+/// safe_run は /lib と /lib64 を実行用ディレクトリにマウントする
+fn check_lib_mounts() -> Result<String, Box<dyn Error>> {
+    for path in ["/lib", "/lib64"] {
+        if !Path::new(path).is_dir() {
+            return Err(format!("{path} is not a directory").into());
+        }
+    }
+    Ok("/lib and /lib64 exist".to_string())
+}
+
+fn check_program() -> Result<String, Box<dyn Error>> {
+    let submission_id = format!("selftest-{}", Uuid::new_v4().hyphenated());
+    let profile = find_profile(&CONFIG.program.default_profile)?;
+    let program = Program::new(&submission_id, &Source::Single(SELF_TEST_SOURCE.to_string()), profile)?;
+    let compile_result = program.compile_result();
+    if !compile_result.exit.success() {
+        return Err(format!("failed to compile the test program: {}", compile_result.stderr.trim()).into());
+    }
+
+    let result = program.run("1 2\n", &KillHandle::default())?;
+    if result.verdict != Verdict::Ok || result.stdout.trim() != "3" {
+        return Err(format!("the test program finished with {:?} and printed {:?} (stderr: {:?})", result.verdict, result.stdout, result.stderr).into());
+    }
+    Ok(format!("compiled and ran the test program in {} ms", result.time_ms))
+}
@@ -0,0 +1,133 @@
+use std::collections::*;
+use std::fmt;
+use std::fs::OpenOptions;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use once_cell::sync::Lazy;
+use serde::Serialize;
+use serde_json::{Map, Value as JsonValue};
+use tracing::field::{Field, Visit};
+use tracing::{span, Event, Subscriber};
+use tracing_subscriber::filter::{EnvFilter, LevelFilter};
+use tracing_subscriber::fmt::writer::BoxMakeWriter;
+use tracing_subscriber::layer::{Context, SubscriberExt};
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::Layer;
+
+use crate::config::*;
+
+/// 提出 ID から、その提出に関するログへの対応
+static SUBMISSION_LOGS: Lazy<Mutex<HashMap<String, Vec<LogEntry>>>> = Lazy::new(|| Mutex::new(HashMap::new()) );
+
+/// 提出ごとに保持するログの 1 件
+#[derive(Clone, Debug, Serialize)]
+pub struct LogEntry {
+    pub timestamp_ms: u64,
+    pub level: String,
+    pub message: String,
+    pub fields: Map<String, JsonValue>,
+}
+
+/// ログの出力を設定する
+///
+/// `log` クレートのログ（actix-web のアクセスログなど）も同じ出力先に送られる。
+/// ログのファイルを開けなければ、設定の項目とパスを含むエラーを返す。
+pub fn init() -> Result<(), String> {
+    let config = &CONFIG.server.log;
+    let writer = match &config.path {
+        Some(path) => {
+            let file = OpenOptions::new().create(true).append(true).open(path).map_err(|err| format!("server.log.path: {path}: {err}") )?;
+            BoxMakeWriter::new(Mutex::new(file))
+        }
+        None => BoxMakeWriter::new(std::io::stderr),
+    };
+    let output = match config.format {
+        LogFormat::Text => tracing_subscriber::fmt::layer().with_ansi(config.path.is_none()).with_writer(writer).boxed(),
+        LogFormat::Json => tracing_subscriber::fmt::layer().json().with_writer(writer).boxed(),
+    };
+
+    tracing_subscriber::registry()
+        .with(output.with_filter(EnvFilter::new(&config.level)))
+        .with(SubmissionLogLayer.with_filter(LevelFilter::DEBUG))
+        .init();
+    Ok(())
+}
+
+/// 提出に関するログを取得する
+pub fn submission_log(submission_id: &str) -> Option<Vec<LogEntry>> {
+    SUBMISSION_LOGS.lock().unwrap().get(submission_id).cloned()
+}
+
+/// 提出に関するログを破棄する
+pub fn remove_submission_log(submission_id: &str) {
+    SUBMISSION_LOGS.lock().unwrap().remove(submission_id);
+}
+
+/// `submission_id` フィールドを持つスパンの中で記録されたイベントを、提出ごとに保持するレイヤー
+struct SubmissionLogLayer;
+
+struct SubmissionId(String);
+
+impl<S> Layer<S> for SubmissionLogLayer where S: Subscriber + for<'a> LookupSpan<'a> {
+    fn on_new_span(&self, attrs: &span::Attributes<'_>, id: &span::Id, ctx: Context<'_, S>) {
+        let mut visitor = FieldVisitor::default();
+        attrs.record(&mut visitor);
+        if let (Some(JsonValue::String(submission_id)), Some(span)) = (visitor.fields.remove("submission_id"), ctx.span(id)) {
+            span.extensions_mut().insert(SubmissionId(submission_id));
+        }
+    }
+
+    fn on_event(&self, event: &Event<'_>, ctx: Context<'_, S>) {
+        let Some(scope) = ctx.event_scope(event) else { return };
+        let Some(submission_id) = scope.into_iter().find_map(|span| span.extensions().get::<SubmissionId>().map(|id| id.0.clone() ) ) else { return };
+
+        let mut visitor = FieldVisitor::default();
+        event.record(&mut visitor);
+        let message = match visitor.fields.remove("message") {
+            Some(JsonValue::String(message)) => message,
+            _ => String::new(),
+        };
+        let entry = LogEntry {
+            timestamp_ms: SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_millis() as u64,
+            level: event.metadata().level().to_string(),
+            message,
+            fields: visitor.fields,
+        };
+
+        let mut logs = SUBMISSION_LOGS.lock().unwrap();
+        let log = logs.entry(submission_id).or_default();
+        if log.len() < CONFIG.server.log.submission_entries {
+            log.push(entry);
+        }
+    }
+}
+
+/// イベントやスパンのフィールドを JSON の値として集める
+#[derive(Default)]
+struct FieldVisitor {
+    fields: Map<String, JsonValue>,
+}
+
+impl Visit for FieldVisitor {
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        self.fields.insert(field.name().to_string(), JsonValue::from(format!("{value:?}")));
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.fields.insert(field.name().to_string(), JsonValue::from(value));
+    }
+
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.fields.insert(field.name().to_string(), JsonValue::from(value));
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.fields.insert(field.name().to_string(), JsonValue::from(value));
+    }
+
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.fields.insert(field.name().to_string(), JsonValue::from(value));
+    }
+}
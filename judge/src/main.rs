@@ -1,45 +1,249 @@
+pub mod archive;
+pub mod auth;
 pub mod config;
+pub mod contest;
+pub mod error;
+pub mod generator;
+pub mod health;
+pub mod logging;
+pub mod metrics;
+pub mod prepare;
+pub mod problem;
 pub mod program;
+pub mod scheduler;
 pub mod server;
+pub mod tls;
+pub mod validator;
 
-static JUDGE_CLIENT: Lazy<JudgeClient> = Lazy::new(|| JudgeClient::new() );
+static JUDGE_CLIENT: Lazy<JudgeClient> = Lazy::new(JudgeClient::new);
+
+/// 再ジャッジのジョブを割り当てるクライアント。参加者の同時に処理できる提出の数を使わないよう、参加者とは分ける
+///
+/// API キーの名前や IP アドレスと重ならないよう、括弧で囲む。
+const REJUDGE_CLIENT: &str = "(rejudge)";
 
 #[derive(Clone, Debug)]
 pub struct SubmissionData {
     submitted_time: Instant,
     submission_id: String,
-    source_code: String,
+    source: Source,
+    profile: String,
     inputs: Vec<String>,
+    priority: Priority,
+    /// 公平なスケジューリングのためのクライアントの識別子
+    client: String,
+    /// コンテストの問題への提出のとき、その問題。`inputs` の代わりに問題のテストケースを使う
+    problem: Option<ProblemSubmission>,
+}
+
+impl SubmissionData {
+    fn test_count(&self) -> usize {
+        match &self.problem {
+            Some(problem) => problem.problem.tests.len(),
+            None => self.inputs.len(),
+        }
+    }
+}
+
+/// コンテストの問題への提出
+#[derive(Clone, Debug)]
+pub struct ProblemSubmission {
+    contest: String,
+    problem: Problem,
+    /// 順位表に記録する参加者。再ジャッジのジョブのクライアントとは異なる
+    participant: String,
+    submitted_at: SystemTime,
+    /// 再ジャッジのとき、再ジャッジの ID と再ジャッジする提出の ID
+    rejudge: Option<RejudgeTarget>,
+}
+
+#[derive(Clone, Debug)]
+pub struct RejudgeTarget {
+    rejudge_id: String,
+    submission_id: String,
+}
+
+/// 提出の処理の状況
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JudgeStatus {
+    Pending,
+    Compiling,
+    CompileError,
+    Running,
+    Finished,
+    InternalError,
+    Cancelled,
 }
 
 #[derive(Clone, Debug, Serialize)]
 pub struct SubmissionStatus {
-    status: String,
+    status: JudgeStatus,
     compile_result: Option<CompilingResult>,
     run_results: Vec<Option<ExecutionResult>>,
+    /// `InternalError` の原因
+    error: Option<String>,
+    /// `Pending` のとき、先にジャッジされる提出の数
+    queue_position: Option<usize>,
+    /// 提出したクライアント
+    #[serde(skip)]
+    client: String,
+    /// コンテストの問題への提出のとき、コンテストと問題の ID
+    #[serde(skip_serializing_if = "Option::is_none")]
+    contest: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    problem: Option<String>,
+    /// 問題への提出の判定と得点。ジャッジが終わると設定される
+    #[serde(skip_serializing_if = "Option::is_none")]
+    verdict: Option<ProblemVerdict>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    score: Option<u64>,
+    /// 問題への提出のとき、ジャッジに使ったテストデータの版
+    #[serde(skip_serializing_if = "Option::is_none")]
+    test_data_version: Option<u64>,
+}
+
+impl SubmissionStatus {
+    /// 待機中またはジャッジ中かどうか
+    fn is_active(&self) -> bool {
+        matches!(self.status, JudgeStatus::Pending | JudgeStatus::Compiling | JudgeStatus::Running)
+    }
 }
 
 #[derive(Deserialize)]
 struct SubmissionRequestData {
-    source_code: String,
+    source_code: Option<String>,
+    files: Option<BTreeMap<String, String>>,
+    language: Option<String>,
+    profile: Option<String>,
+    priority: Option<Priority>,
     inputs: Vec<String>,
 }
 
 #[post("/submit")]
-async fn service_submit(data: web::Json<SubmissionRequestData>) -> impl Responder {
-    let SubmissionRequestData { source_code, inputs } = data.into_inner();
+async fn service_submit(req: HttpRequest, data: web::Json<SubmissionRequestData>) -> Result<HttpResponse, ApiError> {
+    let client = authorize(&req, Scope::Submit)?;
+    let SubmissionRequestData { source_code, files, language, profile, priority, inputs } = data.into_inner();
+
+    let source = match (source_code, files) {
+        (Some(source_code), None) => Source::Single(source_code),
+        (None, Some(files)) => Source::Files(files),
+        _ => return Err(ApiError::new(ErrorCode::InvalidRequest, "exactly one of source_code and files must be given")),
+    };
+
+    let priority = requested_priority(&client, priority)?;
+    let submission_id = submit(&client, source, language, profile, priority, inputs, None)?;
+    Ok(HttpResponse::Ok().body(submission_id))
+}
+
+#[derive(Deserialize)]
+struct SubmissionArchiveQuery {
+    language: Option<String>,
+    profile: Option<String>,
+    priority: Option<Priority>,
+}
+
+/// tar アーカイブで提出する
+///
+/// `inputs/` 以下のファイルはパスの順に入力として扱い、それ以外のファイルはファイルツリーとして扱う。
+#[post("/submit/archive")]
+async fn service_submit_archive(req: HttpRequest, query: web::Query<SubmissionArchiveQuery>, payload: web::Payload) -> Result<HttpResponse, ApiError> {
+    let client = authorize(&req, Scope::Submit)?;
+    let limit = CONFIG.server.limits.payload_bytes;
+    let body = payload.to_bytes_limited(limit).await
+        .map_err(|_| ApiError::new(ErrorCode::PayloadTooLarge, format!("payload must be at most {limit} bytes")) )?
+        .map_err(|err| ApiError::new(ErrorCode::InvalidRequest, err.to_string()) )?;
+
+    let mut files = BTreeMap::new();
+    let mut inputs = BTreeMap::new();
+    let mut archive = tar::Archive::new(&body[..]);
+    let entries = archive.entries().map_err(|err| ApiError::new(ErrorCode::InvalidArchive, err.to_string()) )?;
+    for entry in entries {
+        let Some((path, content)) = read_archive_entry(entry).map_err(|err| ApiError::new(ErrorCode::InvalidArchive, err.to_string()) )? else { continue };
+        match path.strip_prefix("inputs/") {
+            Some(name) => inputs.insert(name.to_string(), content),
+            None => files.insert(path, content),
+        };
+    }
+
+    let SubmissionArchiveQuery { language, profile, priority } = query.into_inner();
+    let priority = requested_priority(&client, priority)?;
+    let submission_id = submit(&client, Source::Files(files), language, profile, priority, inputs.into_values().collect(), None)?;
+    Ok(HttpResponse::Ok().body(submission_id))
+}
+
+fn read_archive_entry(entry: std::io::Result<tar::Entry<&[u8]>>) -> std::io::Result<Option<(String, String)>> {
+    let mut entry = entry?;
+    if !entry.header().entry_type().is_file() {
+        return Ok(None);
+    }
+    let path = entry.path()?.to_string_lossy().trim_start_matches("./").to_string();
+    let mut content = String::new();
+    entry.read_to_string(&mut content)?;
+    Ok(Some((path, content)))
+}
+
+/// クライアントが指定した優先度を確かめる。`practice` 以外の優先度は `admin` スコープを持つクライアントのみが指定できる
+fn requested_priority(client: &Client, priority: Option<Priority>) -> Result<Priority, ApiError> {
+    match priority.unwrap_or_default() {
+        Priority::Practice => Ok(Priority::Practice),
+        priority if client.is_admin() => Ok(priority),
+        priority => Err(ApiError::new(ErrorCode::Forbidden, format!("priority {} requires the admin scope", label(priority)))),
+    }
+}
+
+fn submit(client: &Client, source: Source, language: Option<String>, profile: Option<String>, priority: Priority, inputs: Vec<String>, problem: Option<ProblemSubmission>) -> Result<String, ApiError> {
+    if let Some(language) = language {
+        if language != "rust" {
+            return Err(ApiError::new(ErrorCode::UnsupportedLanguage, format!("unsupported language: {language}")));
+        }
+    }
+
+    let profile = profile.unwrap_or_else(|| CONFIG.program.default_profile.clone() );
+    find_profile(&profile).map_err(|err| ApiError::new(ErrorCode::UnknownProfile, err.to_string()) )?;
+
+    validate_submission(&source, &inputs)?;
 
     let submission_id = Uuid::new_v4().hyphenated().to_string();
     let now = Instant::now();
 
-    JUDGE_CLIENT.submit(SubmissionData {
+    let submission_data = SubmissionData {
         submitted_time: now,
         submission_id: submission_id.clone(),
-        source_code,
+        source,
+        profile,
         inputs,
-    });
+        priority,
+        client: client.name.clone(),
+        problem,
+    };
+    JUDGE_CLIENT.submit_checked(submission_data, |active| check_submission_quota(client, active) )?;
+
+    Ok(submission_id)
+}
+
+/// ソースコードと入力の大きさを制限する
+fn validate_submission(source: &Source, inputs: &[String]) -> Result<(), ApiError> {
+    let limits = &CONFIG.server.limits;
+
+    let source_bytes = match source {
+        Source::Single(source_code) => source_code.len(),
+        Source::Files(files) => files.values().map(String::len).sum(),
+    };
+    if source_bytes > limits.source_bytes {
+        return Err(ApiError::new(ErrorCode::SourceTooLarge, format!("source must be at most {} bytes, but got {source_bytes} bytes", limits.source_bytes)));
+    }
+
+    if inputs.len() > limits.input_count {
+        return Err(ApiError::new(ErrorCode::TooManyInputs, format!("at most {} inputs are allowed, but got {}", limits.input_count, inputs.len())));
+    }
+
+    let input_bytes = inputs.iter().map(String::len).sum::<usize>();
+    if input_bytes > limits.input_bytes {
+        return Err(ApiError::new(ErrorCode::InputTooLarge, format!("inputs must be at most {} bytes in total, but got {input_bytes} bytes", limits.input_bytes)));
+    }
 
-    HttpResponse::Ok().body(submission_id)
+    Ok(())
 }
 
 #[get("/status")]
@@ -48,72 +252,695 @@ async fn service_status_none() -> HttpResponse {
 }
 
 #[get("/status/{submission_id}")]
-async fn service_status(path: web::Path<String>) -> impl Responder {
+async fn service_status(req: HttpRequest, path: web::Path<String>) -> Result<HttpResponse, ApiError> {
+    let client = authorize(&req, Scope::Status)?;
+    let submission_id = path.into_inner();
+    Ok(HttpResponse::Ok().json(find_submission(&client, &submission_id)?))
+}
+
+/// クライアントが参照できる提出の状況を取得する
+///
+/// 他のクライアントの提出は存在しないものとして扱う。
+fn find_submission(client: &Client, submission_id: &str) -> Result<SubmissionStatus, ApiError> {
+    if !valid_submission_id(submission_id) {
+        return Err(ApiError::new(ErrorCode::InvalidId, format!("invalid submission id: {submission_id}")));
+    }
+    JUDGE_CLIENT.status(submission_id)
+        .filter(|status| client.can_access(&status.client) )
+        .ok_or_else(|| ApiError::new(ErrorCode::NotFound, format!("submission {submission_id} is not found")) )
+}
+
+/// 提出を取り消す
+#[delete("/status/{submission_id}")]
+async fn service_cancel(req: HttpRequest, path: web::Path<String>) -> Result<HttpResponse, ApiError> {
+    let client = authorize(&req, Scope::Submit)?;
     let submission_id = path.into_inner();
-    if !valid_submission_id(&submission_id) {
-        return HttpResponse::BadRequest().json(SubmissionStatus {
-            status: "invalid_id".to_string(),
-            compile_result: None,
-            run_results: vec![],
-        });
-    }
-    JUDGE_CLIENT.use_status(&submission_id, |status| {
-        if let Some(status) = status {
-            HttpResponse::Ok().json(status)
-        } else {
-            HttpResponse::NotFound().json(SubmissionStatus {
-                status: "not_found".to_string(),
-                compile_result: None,
-                run_results: vec![],
+    find_submission(&client, &submission_id)?;
+    match JUDGE_CLIENT.cancel(&submission_id) {
+        CancelResult::Cancelled => Ok(HttpResponse::Ok().json(JUDGE_CLIENT.status(&submission_id))),
+        CancelResult::NotFound => Err(ApiError::new(ErrorCode::NotFound, format!("submission {submission_id} is not found"))),
+        CancelResult::AlreadyFinished => Err(ApiError::new(ErrorCode::AlreadyFinished, format!("submission {submission_id} has already finished"))),
+    }
+}
+
+/// コンテストの情報。問題の一覧は開始後（管理者はいつでも）に公開する
+#[get("/contests/{contest_id}")]
+async fn service_contest(req: HttpRequest, path: web::Path<String>) -> Result<HttpResponse, ApiError> {
+    let client = authorize(&req, Scope::Status)?;
+    let contest_id = path.into_inner();
+    let contest = find_contest(&contest_id)?;
+
+    let problems = if client.is_admin() || SystemTime::now() >= contest.start() {
+        let problems = contest.problems.iter()
+            .map(|problem_id| match Problem::load(problem_id) {
+                Ok(problem) => serde_json::json!({ "id": problem_id, "title": problem.manifest.title, "score": problem.full_score() }),
+                Err(err) => {
+                    tracing::error!(%err, contest = contest_id, problem = problem_id, "failed to load the problem");
+                    serde_json::json!({ "id": problem_id })
+                }
             })
+            .collect();
+        Some(problems)
+    } else {
+        None::<Vec<_>>
+    };
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "id": contest_id,
+        "title": contest.title,
+        "start_time": contest.start_time.to_string(),
+        "end_time": contest.end_time.to_string(),
+        "scoring": contest.scoring,
+        "penalty_minutes": contest.penalty_minutes,
+        "freeze_minutes": contest.freeze_minutes,
+        "problems": problems,
+    })))
+}
+
+#[derive(Deserialize)]
+struct ContestSubmissionRequestData {
+    problem: String,
+    source_code: Option<String>,
+    files: Option<BTreeMap<String, String>>,
+    language: Option<String>,
+    profile: Option<String>,
+}
+
+/// コンテストの問題に提出する
+///
+/// 参加者はコンテストの期間内だけ提出できる。管理者はいつでも提出できるが、順位表には載らない。
+#[post("/contests/{contest_id}/submit")]
+async fn service_contest_submit(req: HttpRequest, path: web::Path<String>, data: web::Json<ContestSubmissionRequestData>) -> Result<HttpResponse, ApiError> {
+    let client = authorize(&req, Scope::Submit)?;
+    let contest_id = path.into_inner();
+    let contest = find_contest(&contest_id)?;
+    let ContestSubmissionRequestData { problem, source_code, files, language, profile } = data.into_inner();
+
+    let now = SystemTime::now();
+    if !client.is_admin() {
+        if !contest.participants.contains(&client.name) {
+            return Err(ApiError::new(ErrorCode::NotParticipant, format!("{} is not registered for contest {contest_id}", client.name)));
         }
-    })
+        if !contest.is_running(now) {
+            return Err(ApiError::new(ErrorCode::ContestNotRunning, format!("contest {contest_id} is not running")));
+        }
+    }
+    if !contest.problems.contains(&problem) {
+        return Err(ApiError::new(ErrorCode::NotFound, format!("problem {problem} is not found in contest {contest_id}")));
+    }
+    let problem = Problem::load(&problem).map_err(|err| {
+        tracing::error!(%err, contest = contest_id, problem, "failed to load the problem");
+        ApiError::new(ErrorCode::NotFound, format!("problem {problem} is not available"))
+    })?;
+
+    let source = match (source_code, files) {
+        (Some(source_code), None) => Source::Single(source_code),
+        (None, Some(files)) => Source::Files(files),
+        _ => return Err(ApiError::new(ErrorCode::InvalidRequest, "exactly one of source_code and files must be given")),
+    };
+
+    let profile = profile.unwrap_or_else(|| CONFIG.program.default_profile.clone() );
+    let stored = StoredSource { profile: profile.clone(), source: source.clone() };
+    let problem = ProblemSubmission { contest: contest_id, problem, participant: client.name.clone(), submitted_at: now, rejudge: None };
+    let submission_id = submit(&client, source, language, Some(profile), Priority::Contest, vec![], Some(problem))?;
+    if let Err(err) = contest::save_source(&submission_id, &stored) {
+        tracing::error!(%err, submission_id, "failed to save the source for rejudging");
+    }
+    Ok(HttpResponse::Ok().body(submission_id))
+}
+
+/// 記録された判定から計算した順位表。管理者には凍結後の結果も含めて返す
+#[get("/contests/{contest_id}/scoreboard")]
+async fn service_scoreboard(req: HttpRequest, path: web::Path<String>) -> Result<HttpResponse, ApiError> {
+    let client = authorize(&req, Scope::Status)?;
+    let contest_id = path.into_inner();
+    let contest = find_contest(&contest_id)?;
+    Ok(HttpResponse::Ok().json(contest::scoreboard(&contest_id, contest, client.is_admin())))
+}
+
+#[derive(Deserialize)]
+struct RevealQuery {
+    all: Option<bool>,
+}
+
+/// 終了したコンテストの、凍結により伏せた結果を 1 つ（`all=true` ならすべて）公開する
+#[post("/admin/contests/{contest_id}/reveal")]
+async fn service_reveal(req: HttpRequest, path: web::Path<String>, query: web::Query<RevealQuery>) -> Result<HttpResponse, ApiError> {
+    authorize(&req, Scope::Admin)?;
+    let contest_id = path.into_inner();
+    let contest = find_contest(&contest_id)?;
+    if SystemTime::now() < contest.end() {
+        return Err(ApiError::new(ErrorCode::ContestNotFinished, format!("contest {contest_id} has not finished yet")));
+    }
+
+    let revealed = if query.all.unwrap_or(false) {
+        contest::reveal_all(&contest_id, contest);
+        None
+    } else {
+        contest::reveal_next(&contest_id, contest).map(|(participant, problem)| serde_json::json!({ "participant": participant, "problem": problem }) )
+    };
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "revealed": revealed,
+        "scoreboard": contest::scoreboard(&contest_id, contest, false),
+    })))
+}
+
+fn find_contest(contest_id: &str) -> Result<&'static ContestConfig, ApiError> {
+    CONFIG.contest.contests.get(contest_id).ok_or_else(|| ApiError::new(ErrorCode::NotFound, format!("contest {contest_id} is not found")) )
+}
+
+/// 条件に合うコンテストへの提出を再ジャッジする
+#[post("/admin/rejudge")]
+async fn service_rejudge(req: HttpRequest, filter: web::Json<RejudgeFilter>) -> Result<HttpResponse, ApiError> {
+    authorize(&req, Scope::Admin)?;
+    if filter.is_empty() {
+        return Err(ApiError::new(ErrorCode::InvalidRequest, "at least one condition is required"));
+    }
+    rejudge(&filter)
+}
+
+/// 1 つの提出を再ジャッジする
+#[post("/admin/submissions/{submission_id}/rejudge")]
+async fn service_rejudge_submission(req: HttpRequest, path: web::Path<String>) -> Result<HttpResponse, ApiError> {
+    authorize(&req, Scope::Admin)?;
+    rejudge(&RejudgeFilter { submission_ids: Some(vec![path.into_inner()]), ..Default::default() })
+}
+
+/// 問題へのすべての提出を再ジャッジする
+#[post("/admin/problems/{problem_id}/rejudge")]
+async fn service_rejudge_problem(req: HttpRequest, path: web::Path<String>) -> Result<HttpResponse, ApiError> {
+    authorize(&req, Scope::Admin)?;
+    rejudge(&RejudgeFilter { problem: Some(path.into_inner()), ..Default::default() })
+}
+
+/// 再ジャッジの結果と、判定が変わった提出
+#[get("/admin/rejudges/{rejudge_id}")]
+async fn service_rejudge_report(req: HttpRequest, path: web::Path<String>) -> Result<HttpResponse, ApiError> {
+    authorize(&req, Scope::Admin)?;
+    let rejudge_id = path.into_inner();
+    match contest::rejudge_report(&rejudge_id) {
+        Some(report) => Ok(HttpResponse::Ok().json(report)),
+        None => Err(ApiError::new(ErrorCode::NotFound, format!("rejudge {rejudge_id} is not found"))),
+    }
+}
+
+/// 条件に合う提出を、現在のテストデータで `rejudge` の優先度でジャッジし直す
+///
+/// ソースコードが保存されていない提出などは飛ばし、その理由を返す。
+fn rejudge(filter: &RejudgeFilter) -> Result<HttpResponse, ApiError> {
+    let targets = contest::find_submissions(filter);
+    if targets.is_empty() {
+        return Err(ApiError::new(ErrorCode::NotFound, "no contest submissions match the conditions"));
+    }
+
+    let rejudge_id = Uuid::new_v4().hyphenated().to_string();
+    let mut queued = vec![];
+    let mut skipped = vec![];
+    for target in targets {
+        match queue_rejudge(&rejudge_id, &target) {
+            Ok(submission_id) => queued.push((target.submission_id, submission_id)),
+            Err(err) => skipped.push(serde_json::json!({ "submission_id": target.submission_id, "reason": err.to_string() })),
+        }
+    }
+    tracing::info!(rejudge_id, queued = queued.len(), skipped = skipped.len(), "started a rejudge");
+    let queued_count = queued.len();
+    contest::start_rejudge(&rejudge_id, queued);
+    Ok(HttpResponse::Ok().json(serde_json::json!({ "rejudge_id": rejudge_id, "queued": queued_count, "skipped": skipped })))
+}
+
+fn queue_rejudge(rejudge_id: &str, target: &ContestSubmission) -> Result<String, Box<dyn std::error::Error>> {
+    let StoredSource { profile, source } = contest::load_source(&target.submission_id).map_err(|err| format!("failed to load the source: {err}") )?;
+    find_profile(&profile)?;
+    let problem = Problem::load(&target.problem)?;
+
+    let submission_id = Uuid::new_v4().hyphenated().to_string();
+    JUDGE_CLIENT.submit(SubmissionData {
+        submitted_time: Instant::now(),
+        submission_id: submission_id.clone(),
+        source,
+        profile,
+        inputs: vec![],
+        priority: Priority::Rejudge,
+        client: REJUDGE_CLIENT.to_string(),
+        problem: Some(ProblemSubmission {
+            contest: target.contest.clone(),
+            problem,
+            participant: target.participant.clone(),
+            submitted_at: UNIX_EPOCH + Duration::from_millis(target.submitted_at),
+            rejudge: Some(RejudgeTarget { rejudge_id: rejudge_id.to_string(), submission_id: target.submission_id.clone() }),
+        }),
+    });
+    Ok(submission_id)
+}
+
+#[derive(Serialize)]
+struct ProblemData {
+    id: String,
+    manifest: problem::ProblemManifest,
+    test_data: problem::TestData,
+}
+
+/// 問題の `problem.toml` とテストデータの版
+#[get("/admin/problems/{problem_id}")]
+async fn service_problem(req: HttpRequest, path: web::Path<String>) -> Result<HttpResponse, ApiError> {
+    authorize(&req, Scope::Admin)?;
+    let problem_id = path.into_inner();
+    find_problem(&problem_id)?;
+    let manifest = problem::read_manifest(&problem_id).map_err(test_data_error)?;
+    let test_data = problem::read_test_data(&problem_id).map_err(test_data_error)?;
+    Ok(HttpResponse::Ok().json(ProblemData { id: problem_id, manifest, test_data }))
+}
+
+/// 問題を作成するか、`problem.toml` を置き換える
+#[put("/admin/problems/{problem_id}")]
+async fn service_put_problem(req: HttpRequest, path: web::Path<String>, manifest: web::Json<problem::ProblemManifest>) -> Result<HttpResponse, ApiError> {
+    authorize(&req, Scope::Admin)?;
+    let problem_id = path.into_inner();
+    if !config::valid_problem_id(&problem_id) {
+        return Err(ApiError::new(ErrorCode::InvalidId, format!("invalid problem id: {problem_id}")));
+    }
+    let manifest = manifest.into_inner();
+    let id = problem_id.clone();
+    web::block(move || problem::save_manifest(&id, &manifest).map_err(test_data_error) ).await
+        .map_err(|err| ApiError::new(ErrorCode::InternalError, err.to_string()) )??;
+    tracing::info!(problem = problem_id, "saved the problem");
+    Ok(HttpResponse::Ok().json(serde_json::json!({ "id": problem_id })))
+}
+
+/// テストデータを tar、tar.gz、zip のアーカイブで置き換える
+#[put("/admin/problems/{problem_id}/tests")]
+async fn service_put_tests(req: HttpRequest, path: web::Path<String>, payload: web::Payload) -> Result<HttpResponse, ApiError> {
+    authorize(&req, Scope::Admin)?;
+    let problem_id = path.into_inner();
+    find_problem(&problem_id)?;
+    let body = read_payload(payload).await?;
+    let id = problem_id.clone();
+    let test_data = web::block(move || {
+        let files = archive::read_files(&body, 2 * CONFIG.problem.max_test_count, CONFIG.problem.max_test_bytes, CONFIG.problem.max_archive_bytes).map_err(|err| ApiError::new(ErrorCode::InvalidArchive, err.to_string()) )?;
+        problem::replace_tests(&id, files).map_err(test_data_error)
+    }).await.map_err(|err| ApiError::new(ErrorCode::InternalError, err.to_string()) )??;
+    tracing::info!(problem = problem_id, version = test_data.version, tests = test_data.tests.len(), "replaced the test data");
+    Ok(HttpResponse::Ok().json(test_data))
+}
+
+/// テストケースのファイルを 1 つ追加または置き換える。ファイル名は `<名前>.in` か `<名前>.out`
+#[put("/admin/problems/{problem_id}/tests/{file_name}")]
+async fn service_put_test_file(req: HttpRequest, path: web::Path<(String, String)>, payload: web::Payload) -> Result<HttpResponse, ApiError> {
+    authorize(&req, Scope::Admin)?;
+    let (problem_id, file_name) = path.into_inner();
+    find_problem(&problem_id)?;
+    let body = read_payload(payload).await?;
+    let id = problem_id.clone();
+    let test_data = web::block(move || problem::put_test_file(&id, &file_name, &body).map_err(test_data_error) ).await
+        .map_err(|err| ApiError::new(ErrorCode::InternalError, err.to_string()) )??;
+    tracing::info!(problem = problem_id, version = test_data.version, "updated a test file");
+    Ok(HttpResponse::Ok().json(test_data))
+}
+
+/// テストケースの `.in` と `.out` を削除する
+#[delete("/admin/problems/{problem_id}/tests/{test_name}")]
+async fn service_delete_test(req: HttpRequest, path: web::Path<(String, String)>) -> Result<HttpResponse, ApiError> {
+    authorize(&req, Scope::Admin)?;
+    let (problem_id, test_name) = path.into_inner();
+    find_problem(&problem_id)?;
+    let tests_dir = problem::problem_dir(&problem_id).join("tests");
+    if !config::valid_problem_id(&test_name) || !tests_dir.join(format!("{test_name}.in")).is_file() {
+        return Err(ApiError::new(ErrorCode::NotFound, format!("test case {test_name} is not found")));
+    }
+    let id = problem_id.clone();
+    let test_data = web::block(move || problem::delete_test(&id, &test_name).map_err(test_data_error) ).await
+        .map_err(|err| ApiError::new(ErrorCode::InternalError, err.to_string()) )??;
+    tracing::info!(problem = problem_id, version = test_data.version, "deleted a test case");
+    Ok(HttpResponse::Ok().json(test_data))
+}
+
+/// 問題の準備を始める。解をコンパイルし、主な解からテストケースの出力を作り、ほかの解の判定を確かめる
+#[post("/admin/problems/{problem_id}/prepare")]
+async fn service_prepare(req: HttpRequest, path: web::Path<String>) -> Result<HttpResponse, ApiError> {
+    authorize(&req, Scope::Admin)?;
+    let problem_id = path.into_inner();
+    find_problem(&problem_id)?;
+    let job_id = prepare::start(&problem_id).map_err(|err| match err.downcast_ref::<prepare::AlreadyPreparing>() {
+        Some(running) => ApiError::new(ErrorCode::PrepareRunning, err.to_string()).with_details(serde_json::json!({ "job_id": running.0 })),
+        None => test_data_error(err),
+    })?;
+    Ok(HttpResponse::Ok().json(serde_json::json!({ "job_id": job_id })))
+}
+
+/// 問題の準備の状況と、解ごとの判定
+#[get("/admin/prepares/{job_id}")]
+async fn service_prepare_job(req: HttpRequest, path: web::Path<String>) -> Result<HttpResponse, ApiError> {
+    authorize(&req, Scope::Admin)?;
+    let job_id = path.into_inner();
+    match prepare::find(&job_id) {
+        Some(job) => Ok(HttpResponse::Ok().json(job)),
+        None => Err(ApiError::new(ErrorCode::NotFound, format!("prepare job {job_id} is not found"))),
+    }
+}
+
+/// 検証器などのファイルを問題のディレクトリに置く
+#[put("/admin/problems/{problem_id}/files/{name}")]
+async fn service_put_problem_file(req: HttpRequest, path: web::Path<(String, String)>, payload: web::Payload) -> Result<HttpResponse, ApiError> {
+    authorize(&req, Scope::Admin)?;
+    let (problem_id, name) = path.into_inner();
+    find_problem(&problem_id)?;
+    let body = read_payload(payload).await?;
+    let id = problem_id.clone();
+    let file_name = name.clone();
+    web::block(move || problem::put_file(&id, &file_name, &body).map_err(test_data_error) ).await
+        .map_err(|err| ApiError::new(ErrorCode::InternalError, err.to_string()) )??;
+    tracing::info!(problem = problem_id, name, "saved a problem file");
+    Ok(HttpResponse::Ok().json(serde_json::json!({ "id": problem_id, "name": name })))
+}
+
+/// 問題のディレクトリに置いたファイルを削除する
+#[delete("/admin/problems/{problem_id}/files/{name}")]
+async fn service_delete_problem_file(req: HttpRequest, path: web::Path<(String, String)>) -> Result<HttpResponse, ApiError> {
+    authorize(&req, Scope::Admin)?;
+    let (problem_id, name) = path.into_inner();
+    find_problem(&problem_id)?;
+    if !problem::problem_file_path(&problem_id, &name).is_ok_and(|path| path.is_file() ) {
+        return Err(ApiError::new(ErrorCode::NotFound, format!("file {name} is not found")));
+    }
+    let id = problem_id.clone();
+    let file_name = name.clone();
+    web::block(move || problem::delete_file(&id, &file_name).map_err(test_data_error) ).await
+        .map_err(|err| ApiError::new(ErrorCode::InternalError, err.to_string()) )??;
+    tracing::info!(problem = problem_id, name, "deleted a problem file");
+    Ok(HttpResponse::Ok().json(serde_json::json!({ "id": problem_id, "name": name })))
+}
+
+fn find_problem(problem_id: &str) -> Result<(), ApiError> {
+    if !config::valid_problem_id(problem_id) {
+        return Err(ApiError::new(ErrorCode::InvalidId, format!("invalid problem id: {problem_id}")));
+    }
+    if !problem::problem_dir(problem_id).join("problem.toml").is_file() {
+        return Err(ApiError::new(ErrorCode::NotFound, format!("problem {problem_id} is not found")));
+    }
+    Ok(())
+}
+
+async fn read_payload(payload: web::Payload) -> Result<web::Bytes, ApiError> {
+    let limit = CONFIG.server.limits.payload_bytes;
+    payload.to_bytes_limited(limit).await
+        .map_err(|_| ApiError::new(ErrorCode::PayloadTooLarge, format!("payload must be at most {limit} bytes")) )?
+        .map_err(|err| ApiError::new(ErrorCode::InvalidRequest, err.to_string()) )
+}
+
+fn test_data_error(err: Box<dyn std::error::Error>) -> ApiError {
+    if let Some(failure) = err.downcast_ref::<validator::ValidationFailure>() {
+        ApiError::new(ErrorCode::InvalidTestData, err.to_string()).with_details(failure)
+    } else if err.is::<problem::TestDataError>() {
+        ApiError::new(ErrorCode::InvalidTestData, err.to_string())
+    } else {
+        tracing::error!(%err, "failed to update the test data");
+        ApiError::new(ErrorCode::InternalError, err.to_string())
+    }
+}
+
+/// Prometheus 形式の計測値
+#[get("/metrics")]
+async fn service_metrics(req: HttpRequest) -> Result<HttpResponse, ApiError> {
+    authorize(&req, Scope::Metrics)?;
+    Ok(HttpResponse::Ok().content_type("text/plain; version=0.0.4").body(METRICS.encode()))
+}
+
+/// プロセスが応答できるかどうか
+#[get("/healthz")]
+async fn service_healthz() -> HttpResponse {
+    HttpResponse::Ok().json(serde_json::json!({ "status": "ok" }))
+}
+
+/// 最後の自己診断の結果。ジャッジできる状態でなければ 503 を返す
+#[get("/readyz")]
+async fn service_readyz() -> HttpResponse {
+    match health::last_report() {
+        Some(report) if report.ready => HttpResponse::Ok().json(report),
+        Some(report) => HttpResponse::ServiceUnavailable().json(report),
+        None => HttpResponse::ServiceUnavailable().json(serde_json::json!({ "ready": false, "checks": [] })),
+    }
+}
+
+/// 自己診断をやり直す
+#[post("/admin/selftest")]
+async fn service_selftest(req: HttpRequest) -> Result<HttpResponse, actix_web::Error> {
+    authorize(&req, Scope::Admin)?;
+    let report = web::block(health::self_test).await?;
+    Ok(HttpResponse::Ok().json(report))
+}
+
+/// 提出のジャッジのログ
+#[get("/admin/submissions/{submission_id}/log")]
+async fn service_submission_log(req: HttpRequest, path: web::Path<String>) -> Result<HttpResponse, ApiError> {
+    authorize(&req, Scope::Admin)?;
+    let submission_id = path.into_inner();
+    match logging::submission_log(&submission_id) {
+        Some(log) => Ok(HttpResponse::Ok().json(log)),
+        None => Err(ApiError::new(ErrorCode::NotFound, format!("log of submission {submission_id} is not found"))),
+    }
+}
+
+/// 設定を再読み込みする
+#[post("/admin/reload")]
+async fn service_reload(req: HttpRequest) -> Result<HttpResponse, ApiError> {
+    authorize(&req, Scope::Admin)?;
+    reload_config().map_err(|err| ApiError::new(ErrorCode::InvalidConfig, err.to_string()) )?;
+    Ok(HttpResponse::Ok().json(serde_json::json!({ "reloaded": true })))
+}
+
+/// 設定と TLS の証明書を読み直す。ジャッジ中の提出は、ジャッジを始めたときの設定のまま最後まで処理される
+fn reload_config() -> Result<(), Box<dyn std::error::Error>> {
+    let config = config::reload();
+    match &config {
+        Ok(()) => tracing::info!("reloaded the config"),
+        Err(err) => tracing::error!(%err, "failed to reload the config; keeping the current config"),
+    }
+    // 設定の読み込みに失敗しても、証明書の更新は行う
+    let certificates = tls::reload_certificates();
+    config?;
+    Ok(certificates?)
+}
+
+/// 設定に従って CORS のミドルウェアを作る。値は設定の読み込み時に検証している
+fn cors(config: &CorsConfig) -> Cors {
+    let mut cors = Cors::default();
+    for origin in &config.allowed_origins {
+        cors = if origin == "*" { cors.allow_any_origin() } else { cors.allowed_origin(origin) };
+    }
+    if config.allowed_methods.iter().any(|method| method == "*" ) {
+        cors = cors.allow_any_method();
+    } else {
+        cors = cors.allowed_methods(config.allowed_methods.iter().map(String::as_str));
+    }
+    if config.allowed_headers.iter().any(|header| header == "*" ) {
+        cors = cors.allow_any_header();
+    } else {
+        cors = cors.allowed_headers(config.allowed_headers.iter().map(String::as_str));
+    }
+    if config.allow_credentials {
+        cors = cors.supports_credentials();
+    }
+    cors.max_age(config.max_age)
+}
+
+fn json_error(err: JsonPayloadError, _req: &HttpRequest) -> actix_web::Error {
+    match err {
+        JsonPayloadError::Overflow { limit } | JsonPayloadError::OverflowKnownLength { limit, .. } => {
+            ApiError::new(ErrorCode::PayloadTooLarge, format!("payload must be at most {limit} bytes")).into()
+        }
+        err => ApiError::new(ErrorCode::InvalidRequest, err.to_string()).into(),
+    }
 }
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
-    let mut certs_file = BufReader::new(File::open(&CONFIG.server.ssl_cert_path)?);
-    let mut key_file = BufReader::new(File::open(&CONFIG.server.ssl_key_path)?);
+    // 引数の取得
+    let (config_path, check_config) = {
+        let mut config_path = PathBuf::from(config::DEFAULT_CONFIG_PATH);
+        let mut check_config = false;
+        let mut args = std::env::args().skip(1);
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "-c" | "--config" => config_path = args.next().map(PathBuf::from).unwrap_or_else(|| usage() ),
+                "check-config" => check_config = true,
+                _ => usage(),
+            }
+        }
+        (config_path, check_config)
+    };
 
-    let tls_certs = rustls_pemfile::certs(&mut certs_file).collect::<std::result::Result<Vec<_>, _>>()?;
-    let tls_key = rustls_pemfile::pkcs8_private_keys(&mut key_file).next().unwrap()?;
+    // 設定の読み込みと検証
+    match config::load(&config_path) {
+        Ok(_) if check_config => {
+            println!("{}: OK", config_path.display());
+            return Ok(());
+        }
+        Ok(config) => config::init(config, &config_path),
+        Err(err) => {
+            eprintln!("invalid config: {err}");
+            std::process::exit(1);
+        }
+    }
+
+    if let Err(err) = logging::init() {
+        eprintln!("invalid config: {err}");
+        std::process::exit(1);
+    }
+
+    if let Err(err) = contest::load() {
+        tracing::error!(%err, "failed to load the contest submissions");
+        std::process::exit(1);
+    }
+
+    // SIGHUP で設定を再読み込みする
+    rt::spawn(async {
+        let mut hangup = rt::signal::unix::signal(rt::signal::unix::SignalKind::hangup()).unwrap();
+        while hangup.recv().await.is_some() {
+            let _ = reload_config();
+        }
+    });
 
-    let tls_config = rustls::ServerConfig::builder()
-        .with_no_client_auth()
-        .with_single_cert(tls_certs, rustls::pki_types::PrivateKeyDer::Pkcs8(tls_key))
-        .unwrap();
+    // 起動時の自己診断。終わるまで /readyz は 503 を返す
+    std::thread::spawn(|| {
+        for check in health::self_test().checks.iter().filter(|check| !check.ok ) {
+            tracing::error!(check = check.name, message = %check.message, "self test failed");
+        }
+    });
 
-    HttpServer::new(move || {
-        let cors = Cors::default()
-            .allow_any_origin();
+    let mut server = HttpServer::new(move || {
+        let cors = cors(&CONFIG.server.cors);
+        let json_config = web::JsonConfig::default()
+            .limit(CONFIG.server.limits.payload_bytes)
+            .error_handler(json_error);
         let mut app = App::new()
             .wrap(cors)
+            .wrap(middleware::Logger::default())
+            .app_data(json_config)
             .app_data(JudgeClient::new())
             .service(service_submit)
-            .service(service_status);
-        for (path, file) in &CONFIG.server.public_files {
-            app = app.service(actix_files::Files::new(path, file.to_string()));
+            .service(service_submit_archive)
+            .service(service_status)
+            .service(service_cancel)
+            .service(service_contest)
+            .service(service_contest_submit)
+            .service(service_scoreboard)
+            .service(service_reveal)
+            .service(service_rejudge)
+            .service(service_rejudge_submission)
+            .service(service_rejudge_problem)
+            .service(service_rejudge_report)
+            .service(service_problem)
+            .service(service_put_problem)
+            .service(service_put_tests)
+            .service(service_put_test_file)
+            .service(service_delete_test)
+            .service(service_put_problem_file)
+            .service(service_delete_problem_file)
+            .service(service_prepare)
+            .service(service_prepare_job)
+            .service(service_metrics)
+            .service(service_healthz)
+            .service(service_readyz)
+            .service(service_selftest)
+            .service(service_submission_log)
+            .service(service_reload);
+        for (path, dir) in &CONFIG.server.public_files {
+            // 値が文字列であることは設定の読み込み時に検証している
+            app = app.service(actix_files::Files::new(path, dir.as_str().unwrap_or_default()).index_file("index.html"));
         }
         app
     })
-        .bind_rustls_0_22(&CONFIG.server.addr_port, tls_config)?
-        .run()
-        .await
+        .on_connect(tls::on_connect);
+
+    for listener in &CONFIG.server.listeners {
+        server = match listener {
+            ListenerConfig::Https { addr_port, ssl_cert_path, ssl_key_path, client_auth } => {
+                server.bind_rustls_0_22(addr_port, tls::server_config(ssl_cert_path, ssl_key_path, client_auth.as_ref())?)?
+            }
+            ListenerConfig::Http { addr_port } => server.bind(addr_port)?,
+            ListenerConfig::Unix { path, mode } => {
+                // 前回の起動で残ったソケットファイルを削除する
+                if metadata(path).is_ok_and(|metadata| metadata.file_type().is_socket() ) {
+                    remove_file(path)?;
+                }
+                let server = server.bind_uds(path)?;
+                if let Some(mode) = mode {
+                    set_permissions(path, Permissions::from_mode(*mode))?;
+                }
+                server
+            }
+        };
+    }
+
+    server.run().await
+}
+
+fn usage() -> ! {
+    println!("Usage: {} [--config CONFIG_TOML] [check-config]", std::env::current_exe().unwrap().to_string_lossy());
+    println!("Note: CONFIG_TOML defaults to {}", config::DEFAULT_CONFIG_PATH);
+    println!("Note: check-config only validates the config and exits");
+    println!("Note: environment variables like {}PROGRAM__TIME_LIMIT=5 override the config", config::ENV_PREFIX);
+    std::process::exit(2)
 }
 
 fn valid_submission_id(submission_id: &str) -> bool {
     Uuid::parse_str(submission_id).is_ok()
 }
 
-use std::time::Instant;
+use std::collections::BTreeMap;
+use std::io::Read;
+use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+use std::path::PathBuf;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 use actix_web::*;
+use actix_web::error::JsonPayloadError;
 use actix_cors::*;
-use config::CONFIG;
+use auth::{authorize, check_submission_quota, Client};
+use contest::{ContestSubmission, RejudgeFilter, StoredSource};
+use config::{ContestConfig, CorsConfig, ListenerConfig, Scope, CONFIG};
+use crate::error::{ApiError, ErrorCode};
+use metrics::{label, METRICS};
 use once_cell::sync::Lazy;
-use program::compile::CompilingResult;
+use program::compile::{find_profile, CompilingResult};
 use program::execute::ExecutionResult;
+use problem::{Problem, ProblemVerdict};
+use program::Source;
+use scheduler::Priority;
 use serde::*;
-use server::JudgeClient;
+use server::{CancelResult, JudgeClient};
 use uuid::*;
-use std::io::BufReader;
-use std::fs::*;
\ No newline at end of file
+use std::fs::*;
+
+#[cfg(test)]
+mod tests {
+    use super::{config, validate_submission, ApiError, BTreeMap, ErrorCode, Source, CONFIG};
+
+    fn error_code(result: Result<(), ApiError>) -> Option<ErrorCode> {
+        result.err().map(|err| err.code )
+    }
+
+    #[test]
+    fn submissions_are_limited_by_source_size() {
+        config::init_test_config();
+        let limit = CONFIG.server.limits.source_bytes;
+        assert_eq!(error_code(validate_submission(&Source::Single("a".repeat(limit)), &[])), None);
+        assert_eq!(error_code(validate_submission(&Source::Single("a".repeat(limit + 1)), &[])), Some(ErrorCode::SourceTooLarge));
+
+        // 複数ファイルの提出は合計の大きさで制限する
+        let files = BTreeMap::from([("src/main.rs".to_string(), "a".repeat(limit / 2 + 1)), ("src/lib.rs".to_string(), "a".repeat(limit / 2))]);
+        assert_eq!(error_code(validate_submission(&Source::Files(files), &[])), Some(ErrorCode::SourceTooLarge));
+    }
+
+    #[test]
+    fn submissions_are_limited_by_input_count_and_size() {
+        config::init_test_config();
+        let limits = &CONFIG.server.limits;
+        let source = Source::Single(String::new());
+        assert_eq!(error_code(validate_submission(&source, &vec![String::new(); limits.input_count])), None);
+        assert_eq!(error_code(validate_submission(&source, &vec![String::new(); limits.input_count + 1])), Some(ErrorCode::TooManyInputs));
+
+        let half = "a".repeat(limits.input_bytes / 2);
+        assert_eq!(error_code(validate_submission(&source, &[half.clone(), half.clone()])), None);
+        assert_eq!(error_code(validate_submission(&source, &[half.clone(), half, "a".to_string()])), Some(ErrorCode::InputTooLarge));
+    }
+}
\ No newline at end of file
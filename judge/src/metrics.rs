@@ -0,0 +1,75 @@
+use once_cell::sync::Lazy;
+use prometheus::*;
+use serde::Serialize;
+
+pub static METRICS: Lazy<Metrics> = Lazy::new(Metrics::new);
+
+/// Prometheus 形式で公開する計測値
+pub struct Metrics {
+    registry: Registry,
+    /// 待機中の提出の数
+    pub queue_length: IntGauge,
+    /// 各状況に遷移した提出の数
+    pub submissions: IntCounterVec,
+    /// 判定ごとの実行結果の数
+    pub verdicts: IntCounterVec,
+    pub compile_duration: Histogram,
+    pub run_duration: Histogram,
+    /// ジャッジ中なら 1
+    pub worker_busy: IntGauge,
+    /// ジャッジに費やした時間の合計。増加率が稼働率になる
+    pub worker_busy_seconds: Counter,
+    /// サンドボックスでの実行の失敗の数
+    pub sandbox_failures: IntCounterVec,
+    /// キャッシュごとの、キャッシュにあった数となかった数
+    pub cache_hits: IntCounterVec,
+    pub cache_misses: IntCounterVec,
+}
+
+impl Metrics {
+    fn new() -> Self {
+        let registry = Registry::new_custom(Some("judge".to_string()), None).unwrap();
+        let metrics = Self {
+            queue_length: IntGauge::new("queue_length", "Number of submissions waiting to be judged").unwrap(),
+            submissions: IntCounterVec::new(Opts::new("submissions_total", "Number of submissions that entered each status"), &["status"]).unwrap(),
+            verdicts: IntCounterVec::new(Opts::new("verdicts_total", "Number of test runs by verdict"), &["verdict"]).unwrap(),
+            compile_duration: Histogram::with_opts(HistogramOpts::new("compile_duration_seconds", "Time spent compiling a submission")
+                .buckets(vec![0.5, 1.0, 2.0, 5.0, 10.0, 20.0, 30.0, 60.0, 120.0])).unwrap(),
+            run_duration: Histogram::with_opts(HistogramOpts::new("run_duration_seconds", "Time spent running a submission on one input")
+                .buckets(vec![0.01, 0.05, 0.1, 0.25, 0.5, 1.0, 2.0, 5.0, 10.0])).unwrap(),
+            worker_busy: IntGauge::new("worker_busy", "Whether the judge worker is judging a submission").unwrap(),
+            worker_busy_seconds: Counter::new("worker_busy_seconds_total", "Time the judge worker spent judging submissions").unwrap(),
+            sandbox_failures: IntCounterVec::new(Opts::new("sandbox_failures_total", "Number of failures to run a program in the sandbox"), &["reason"]).unwrap(),
+            cache_hits: IntCounterVec::new(Opts::new("cache_hits_total", "Number of lookups found in each cache"), &["cache"]).unwrap(),
+            cache_misses: IntCounterVec::new(Opts::new("cache_misses_total", "Number of lookups not found in each cache"), &["cache"]).unwrap(),
+            registry,
+        };
+        metrics.registry.register(Box::new(metrics.queue_length.clone())).unwrap();
+        metrics.registry.register(Box::new(metrics.submissions.clone())).unwrap();
+        metrics.registry.register(Box::new(metrics.verdicts.clone())).unwrap();
+        metrics.registry.register(Box::new(metrics.compile_duration.clone())).unwrap();
+        metrics.registry.register(Box::new(metrics.run_duration.clone())).unwrap();
+        metrics.registry.register(Box::new(metrics.worker_busy.clone())).unwrap();
+        metrics.registry.register(Box::new(metrics.worker_busy_seconds.clone())).unwrap();
+        metrics.registry.register(Box::new(metrics.sandbox_failures.clone())).unwrap();
+        metrics.registry.register(Box::new(metrics.cache_hits.clone())).unwrap();
+        metrics.registry.register(Box::new(metrics.cache_misses.clone())).unwrap();
+        metrics
+    }
+
+    /// キャッシュを引いた結果を数える
+    pub fn cache_lookup(&self, cache: &str, hit: bool) {
+        let counter = if hit { &self.cache_hits } else { &self.cache_misses };
+        counter.with_label_values(&[cache]).inc();
+    }
+
+    /// Prometheus のテキスト形式に変換する
+    pub fn encode(&self) -> String {
+        TextEncoder::new().encode_to_string(&self.registry.gather()).unwrap()
+    }
+}
+
+/// `JudgeStatus` や `Verdict` を API と同じ名前のラベルにする
+pub fn label(value: impl Serialize) -> String {
+    serde_json::to_value(value).ok().and_then(|value| value.as_str().map(str::to_string) ).unwrap_or_default()
+}
@@ -0,0 +1,330 @@
+use std::collections::*;
+use std::error::Error;
+use std::fmt;
+use std::fs::*;
+use std::sync::Mutex;
+use std::thread;
+
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use tracing::{error, info, info_span};
+use uuid::Uuid;
+
+use crate::config::{self, *};
+use crate::generator;
+use crate::metrics::label;
+use crate::problem::{self, check_output, problem_file_path, ProblemManifest, ProblemVerdict, TestDataError};
+use crate::program::compile::find_profile;
+use crate::program::execute::{KillHandle, Verdict};
+use crate::program::{Program, Source, SourceError};
+use crate::validator::Validator;
+
+/// 問題の準備の状況。メモリ上にのみ保持する
+static JOBS: Lazy<Mutex<HashMap<String, PrepareJob>>> = Lazy::new(|| Mutex::new(HashMap::new()) );
+
+/// `problem.toml` の `[[solutions]]`
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct SolutionConfig {
+    /// 解のソースコード。`PUT /admin/problems/{problem_id}/files/{name}` で置いたファイルの名前
+    pub source: String,
+    /// 期待する判定
+    pub verdict: ProblemVerdict,
+    /// テストケースの出力を作る解。`AC` の解のうちちょうど 1 つに指定する
+    #[serde(default)]
+    pub main: bool,
+}
+
+/// `problem.toml` の解の指定を確かめる
+pub fn validate_solutions(solutions: &[SolutionConfig]) -> Result<(), TestDataError> {
+    for solution in solutions {
+        if !problem::valid_file_name(&solution.source) {
+            return Err(TestDataError(format!("invalid file name: {}", solution.source)));
+        }
+        if matches!(solution.verdict, ProblemVerdict::CompileError | ProblemVerdict::InternalError) {
+            return Err(TestDataError(format!("solution {} cannot be expected to get {}", solution.source, label(solution.verdict))));
+        }
+        if solution.main && solution.verdict != ProblemVerdict::Accepted {
+            return Err(TestDataError(format!("main solution {} must be expected to get AC", solution.source)));
+        }
+    }
+    if !solutions.is_empty() && solutions.iter().filter(|solution| solution.main ).count() != 1 {
+        return Err(TestDataError("exactly one solution must be the main solution".to_string()));
+    }
+    Ok(())
+}
+
+/// 同じ問題の準備がすでに実行中のときのエラー。実行中のジョブの ID を持つ
+#[derive(Debug)]
+pub struct AlreadyPreparing(pub String);
+
+impl fmt::Display for AlreadyPreparing {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "the problem is already being prepared by job {}", self.0)
+    }
+}
+
+impl Error for AlreadyPreparing {}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JobStatus {
+    Running,
+    Succeeded,
+    Failed,
+}
+
+/// 問題の準備の結果
+#[derive(Clone, Debug, Serialize)]
+pub struct PrepareJob {
+    pub job_id: String,
+    pub problem: String,
+    pub status: JobStatus,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    pub solutions: Vec<SolutionReport>,
+    /// 生成器から作った入力の数と、そのうちキャッシュから取り出した数
+    pub generated: usize,
+    pub cached: usize,
+    /// 成功したとき、出力を書き込んだテストデータの版
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub test_data_version: Option<u64>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct SolutionReport {
+    pub source: String,
+    pub expected: ProblemVerdict,
+    /// 最初に失敗したテストケースの判定。すべて正解なら `AC`
+    pub verdict: Option<ProblemVerdict>,
+    /// 期待した判定と合っているかどうか
+    pub agreed: Option<bool>,
+    pub max_time_ms: Option<i32>,
+    pub tests: Vec<TestReport>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct TestReport {
+    pub name: String,
+    pub verdict: ProblemVerdict,
+    pub time_ms: i32,
+}
+
+/// 問題の準備を始め、ジョブの ID を返す
+///
+/// スクリプトがあれば生成器で入力を作り、解をすべてコンパイルし、主な解の出力をテストケースの `.out` とする。
+/// 期待した判定と異なる解があれば、出力を書き込まずに失敗する。
+/// 同じ問題の準備が実行中なら、キャッシュと出力を取り合わないように `AlreadyPreparing` を返す。
+pub fn start(problem_id: &str) -> Result<String, Box<dyn Error>> {
+    let manifest = problem::read_manifest(problem_id)?;
+    if !manifest.solutions.iter().any(|solution| solution.main ) {
+        return Err(TestDataError(format!("problem {problem_id} has no main solution")).into());
+    }
+
+    let job_id = Uuid::new_v4().hyphenated().to_string();
+    let solutions = manifest.solutions.iter().map(|solution| SolutionReport {
+        source: solution.source.clone(),
+        expected: solution.verdict,
+        verdict: None,
+        agreed: None,
+        max_time_ms: None,
+        tests: vec![],
+    }).collect();
+    let mut jobs = JOBS.lock().unwrap();
+    if let Some(running) = jobs.values().find(|job| job.problem == problem_id && job.status == JobStatus::Running ) {
+        return Err(AlreadyPreparing(running.job_id.clone()).into());
+    }
+    jobs.insert(job_id.clone(), PrepareJob {
+        job_id: job_id.clone(),
+        problem: problem_id.to_string(),
+        status: JobStatus::Running,
+        error: None,
+        solutions,
+        generated: 0,
+        cached: 0,
+        test_data_version: None,
+    });
+    drop(jobs);
+
+    let (id, problem_id) = (job_id.clone(), problem_id.to_string());
+    thread::spawn(move || {
+        let span = info_span!("prepare", job_id = %id, problem = %problem_id);
+        let _enter = span.enter();
+        info!("started preparing the problem");
+        // 途中で設定が再読み込みされても、始めたときの制限のまま最後まで準備する
+        let result = config::pin(|| run(&id, &problem_id, &manifest) );
+        let mut jobs = JOBS.lock().unwrap();
+        let job = jobs.get_mut(&id).unwrap();
+        match result {
+            Ok(version) => {
+                info!(version, "prepared the problem");
+                job.status = JobStatus::Succeeded;
+                job.test_data_version = Some(version);
+            }
+            Err(err) => {
+                error!(%err, "failed to prepare the problem");
+                job.status = JobStatus::Failed;
+                job.error = Some(err.to_string());
+            }
+        }
+    });
+    Ok(job_id)
+}
+
+pub fn find(job_id: &str) -> Option<PrepareJob> {
+    JOBS.lock().unwrap().get(job_id).cloned()
+}
+
+fn run(job_id: &str, problem_id: &str, manifest: &ProblemManifest) -> Result<u64, Box<dyn Error>> {
+    let uploaded = problem::read_inputs(problem_id)?;
+    let generated = generator::generate(problem_id, manifest)?;
+    if let Some((test, _)) = generated.inputs.iter().find(|(test, _)| uploaded.iter().any(|(name, _)| name == test ) ) {
+        return Err(format!("test case {test} is both uploaded and generated").into());
+    }
+    {
+        let mut jobs = JOBS.lock().unwrap();
+        let job = jobs.get_mut(job_id).unwrap();
+        job.generated = generated.inputs.len();
+        job.cached = generated.cached;
+    }
+
+    let inputs = uploaded.iter().chain(&generated.inputs).cloned().collect::<Vec<_>>();
+    if inputs.is_empty() {
+        return Err(format!("problem {problem_id} has no test inputs").into());
+    }
+    if inputs.len() > CONFIG.problem.max_test_count {
+        return Err(format!("at most {} test cases are allowed, but got {}", CONFIG.problem.max_test_count, inputs.len()).into());
+    }
+    problem::check_subtasks(manifest, &inputs.iter().map(|(test, _)| test.clone() ).collect::<Vec<_>>())?;
+    // アップロードされた入力は検証済みなので、生成した入力だけを検証する
+    if let Some(validator) = Validator::compile(problem_id, manifest)? {
+        for (test, input) in &generated.inputs {
+            validator.validate(manifest, test, input)?;
+        }
+    }
+    let programs = manifest.solutions.iter().map(|solution| compile(problem_id, &solution.source) ).collect::<Result<Vec<_>, _>>()?;
+
+    // 主な解の出力を正しい出力とする
+    let main = manifest.solutions.iter().position(|solution| solution.main ).unwrap();
+    let mut outputs = vec![];
+    let mut tests = vec![];
+    for (test, input) in &inputs {
+        let result = programs[main].run(input, &KillHandle::default())?;
+        if result.verdict != Verdict::Ok {
+            return Err(format!("main solution {} got {} on test {test}", manifest.solutions[main].source, label(ProblemVerdict::of(&[result.verdict]))).into());
+        }
+        tests.push(TestReport { name: test.clone(), verdict: ProblemVerdict::Accepted, time_ms: result.time_ms });
+        outputs.push(result.stdout);
+    }
+    update_report(job_id, main, tests);
+
+    let mut disagreements = vec![];
+    for (i, (solution, program)) in manifest.solutions.iter().zip(&programs).enumerate() {
+        if i == main {
+            continue;
+        }
+        let mut tests = vec![];
+        for ((test, input), expected) in inputs.iter().zip(&outputs) {
+            let result = program.run(input, &KillHandle::default())?;
+            let mut verdict = ProblemVerdict::of(&[result.verdict]);
+            if verdict == ProblemVerdict::Accepted && !check_output(&result.stdout, expected) {
+                verdict = ProblemVerdict::WrongAnswer;
+            }
+            tests.push(TestReport { name: test.clone(), verdict, time_ms: result.time_ms });
+        }
+        if !agrees(solution.verdict, &tests) {
+            let verdict = first_failure(&tests);
+            disagreements.push(format!("{} is expected to get {} but got {}", solution.source, label(solution.verdict), label(verdict)));
+        }
+        update_report(job_id, i, tests);
+    }
+    if !disagreements.is_empty() {
+        return Err(format!("solutions disagree with their expected verdicts: {}", disagreements.join("; ")).into());
+    }
+
+    Ok(problem::write_prepared(problem_id, &uploaded, &generated.inputs, &outputs)?.version)
+}
+
+fn compile(problem_id: &str, source: &str) -> Result<Program, Box<dyn Error>> {
+    let source_code = read_to_string(problem_file_path(problem_id, source)?).map_err(|err| format!("failed to read solution {source}: {err}") )?;
+    let profile = find_profile(&CONFIG.program.default_profile)?;
+    let program = Program::new(&format!("prepare-{}", Uuid::new_v4().hyphenated()), &Source::Single(source_code), profile)
+        .map_err(|err| if err.is::<SourceError>() { format!("invalid solution {source}: {err}").into() } else { err } )?;
+    if !program.compile_result().exit.success() {
+        return Err(format!("failed to compile solution {source}:\n{}", program.compile_result().error_message()).into());
+    }
+    Ok(program)
+}
+
+/// `AC` を期待する解はすべてのテストケースに正解しなければならない。
+/// それ以外の判定を期待する解は、少なくとも 1 つのテストケースでその判定を受け、ほかのテストケースでは正解するかその判定を受けなければならない
+fn agrees(expected: ProblemVerdict, tests: &[TestReport]) -> bool {
+    let all_allowed = tests.iter().all(|test| test.verdict == ProblemVerdict::Accepted || test.verdict == expected );
+    all_allowed && (expected == ProblemVerdict::Accepted || tests.iter().any(|test| test.verdict == expected ))
+}
+
+fn first_failure(tests: &[TestReport]) -> ProblemVerdict {
+    tests.iter().map(|test| test.verdict ).find(|&verdict| verdict != ProblemVerdict::Accepted ).unwrap_or(ProblemVerdict::Accepted)
+}
+
+fn update_report(job_id: &str, solution: usize, tests: Vec<TestReport>) {
+    let mut jobs = JOBS.lock().unwrap();
+    let report = &mut jobs.get_mut(job_id).unwrap().solutions[solution];
+    report.verdict = Some(first_failure(&tests));
+    report.agreed = Some(agrees(report.expected, &tests));
+    report.max_time_ms = tests.iter().map(|test| test.time_ms ).max();
+    report.tests = tests;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn solution(source: &str, verdict: ProblemVerdict, main: bool) -> SolutionConfig {
+        SolutionConfig { source: source.to_string(), verdict, main }
+    }
+
+    fn error(solutions: &[SolutionConfig]) -> String {
+        validate_solutions(solutions).unwrap_err().0
+    }
+
+    #[test]
+    fn solutions_with_one_main_solution_are_valid() {
+        validate_solutions(&[]).unwrap();
+        validate_solutions(&[
+            solution("main.rs", ProblemVerdict::Accepted, true),
+            solution("slow.rs", ProblemVerdict::TimeLimitExceeded, false),
+            solution("wrong.rs", ProblemVerdict::WrongAnswer, false),
+            solution("other.rs", ProblemVerdict::Accepted, false),
+        ]).unwrap();
+    }
+
+    #[test]
+    fn exactly_one_main_solution_is_required() {
+        assert_eq!(error(&[solution("a.rs", ProblemVerdict::Accepted, false)]), "exactly one solution must be the main solution");
+        assert_eq!(
+            error(&[solution("a.rs", ProblemVerdict::Accepted, true), solution("b.rs", ProblemVerdict::Accepted, true)]),
+            "exactly one solution must be the main solution",
+        );
+    }
+
+    #[test]
+    fn main_solution_must_expect_ac() {
+        assert_eq!(error(&[solution("a.rs", ProblemVerdict::WrongAnswer, true)]), "main solution a.rs must be expected to get AC");
+    }
+
+    #[test]
+    fn judge_side_verdicts_cannot_be_expected() {
+        for verdict in [ProblemVerdict::CompileError, ProblemVerdict::InternalError] {
+            let message = error(&[solution("main.rs", ProblemVerdict::Accepted, true), solution("a.rs", verdict, false)]);
+            assert!(message.starts_with("solution a.rs cannot be expected to get "), "{message}");
+        }
+    }
+
+    #[test]
+    fn solution_file_names_are_checked() {
+        for name in ["../main.rs", ".main.rs", "src/main.rs", ""] {
+            assert_eq!(error(&[solution(name, ProblemVerdict::Accepted, true)]), format!("invalid file name: {name}"));
+        }
+    }
+}
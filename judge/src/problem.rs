@@ -0,0 +1,647 @@
+use std::collections::*;
+use std::error::Error;
+use std::fmt;
+use std::fs::*;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, RwLock, RwLockReadGuard, RwLockWriteGuard};
+use std::time::SystemTime;
+
+use once_cell::sync::Lazy;
+use ring::digest::{digest, SHA256};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::contest::unix_millis;
+use crate::generator::{validate_generators, GeneratorConfig};
+use crate::prepare::{validate_solutions, SolutionConfig};
+use crate::validator::{Validator, ValidatorConfig};
+
+use crate::config::*;
+use crate::program::execute::Verdict;
+
+/// 問題のディレクトリの `problem.toml`
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct ProblemManifest {
+    pub title: String,
+    /// 正解したときの得点。小課題がある場合は使わない。省略時は 100
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub score: Option<u64>,
+    /// 小課題。省略時はすべてのテストケースに正解したときだけ得点を得る
+    #[serde(default)]
+    pub subtasks: Vec<Subtask>,
+    /// テストケースの入力の検証器。省略時は検証しない
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub validator: Option<ValidatorConfig>,
+    /// 期待する判定を付けた解。主な解があれば、テストケースの出力は問題の準備で作る
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub solutions: Vec<SolutionConfig>,
+    /// テストケースの入力の生成器
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub generators: Vec<GeneratorConfig>,
+    /// 生成器を実行するスクリプト。`files/` のファイルの名前
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub script: Option<String>,
+}
+
+impl ProblemManifest {
+    /// テストケースの出力を主な解から作るかどうか
+    pub fn generates_outputs(&self) -> bool {
+        self.solutions.iter().any(|solution| solution.main )
+    }
+}
+
+/// 含まれるテストケースにすべて正解したときに得点を得る、テストケースの組
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct Subtask {
+    pub name: String,
+    pub score: u64,
+    /// テストケースの名前のパターン。`*` は任意の文字列に一致する（例: "small_*"）
+    pub tests: Vec<String>,
+}
+
+/// 問題ごとに、テストデータの更新中は読み込まず、ジャッジ中は更新しないようにする
+///
+/// 問題の数は限られるので、ロックはリークさせて `'static` な参照として扱う。
+static TEST_DATA_LOCKS: Lazy<Mutex<HashMap<String, &'static RwLock<()>>>> = Lazy::new(|| Mutex::new(HashMap::new()) );
+
+fn test_data_lock(id: &str) -> &'static RwLock<()> {
+    TEST_DATA_LOCKS.lock().unwrap().entry(id.to_string()).or_insert_with(|| Box::leak(Box::new(RwLock::new(()))) )
+}
+
+type TestDataWriteGuard = RwLockWriteGuard<'static, ()>;
+
+/// 検証器をロックの外でコンパイルしてから、テストデータの書き込みのロックを取る
+///
+/// コンパイルには時間がかかるので、その間ジャッジを止めないようにする。
+/// コンパイルの間に `problem.toml` か検証器のソースが変わっていたら、コンパイルし直す。
+fn write_lock_with_validator(id: &str) -> Result<(ProblemManifest, Option<Validator>, TestDataWriteGuard), Box<dyn Error>> {
+    let validator_source = |manifest: &ProblemManifest| {
+        let config = manifest.validator.as_ref()?;
+        read(problem_file_path(id, &config.source).ok()?).ok()
+    };
+    loop {
+        let manifest = read_manifest(id)?;
+        let source = validator_source(&manifest);
+        let validator = Validator::compile(id, &manifest)?;
+        let lock = test_data_lock(id).write().unwrap();
+        let current = read_manifest(id)?;
+        if toml::to_string(&current)? == toml::to_string(&manifest)? && validator_source(&current) == source {
+            return Ok((manifest, validator, lock));
+        }
+    }
+}
+
+/// 問題のディレクトリから読み込んだ問題
+///
+/// ```text
+/// <problem.dir>/<problem_id>/problem.toml
+/// <problem.dir>/<problem_id>/testdata.json
+/// <problem.dir>/<problem_id>/files/<name>
+/// <problem.dir>/<problem_id>/cache/<hash>.in
+/// <problem.dir>/<problem_id>/tests/<name>.in
+/// <problem.dir>/<problem_id>/tests/<name>.out
+/// ```
+#[derive(Clone, Debug)]
+pub struct Problem {
+    pub id: String,
+    pub manifest: ProblemManifest,
+    /// テストケースの名前。名前の順に並ぶ
+    pub tests: Vec<String>,
+    /// テストデータの版
+    pub test_data_version: u64,
+}
+
+/// テストデータの版と、テストケースごとの大きさとチェックサム。`testdata.json` に保存する
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct TestData {
+    /// API でテストデータを更新するたびに 1 増える。一度も更新していなければ 0
+    pub version: u64,
+    /// 最後に更新した時刻（UNIX 時間のミリ秒）
+    pub updated_at: u64,
+    pub tests: Vec<TestCaseInfo>,
+    /// 問題の準備で生成器から作ったテストケースの名前
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub generated: Vec<String>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct TestCaseInfo {
+    pub name: String,
+    pub input: FileInfo,
+    pub output: FileInfo,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct FileInfo {
+    pub bytes: u64,
+    pub sha256: String,
+}
+
+/// アップロードされたテストデータに起因するエラー
+#[derive(Debug)]
+pub struct TestDataError(pub String);
+
+impl fmt::Display for TestDataError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl Error for TestDataError {}
+
+/// 問題への提出の判定
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub enum ProblemVerdict {
+    #[serde(rename = "AC")]
+    Accepted,
+    #[serde(rename = "WA")]
+    WrongAnswer,
+    #[serde(rename = "TLE")]
+    TimeLimitExceeded,
+    #[serde(rename = "RE")]
+    RuntimeError,
+    #[serde(rename = "CE")]
+    CompileError,
+    /// ジャッジ側の問題。ペナルティには数えない
+    #[serde(rename = "IE")]
+    InternalError,
+}
+
+impl ProblemVerdict {
+    /// テストケースごとの判定から、最初に失敗したテストケースの判定を提出の判定とする
+    pub fn of(verdicts: &[Verdict]) -> Self {
+        match verdicts.iter().find(|&&verdict| verdict != Verdict::Ok ) {
+            None => ProblemVerdict::Accepted,
+            Some(Verdict::Ok) => unreachable!(),
+            Some(Verdict::WrongAnswer) => ProblemVerdict::WrongAnswer,
+            Some(Verdict::TimeLimitExceeded) => ProblemVerdict::TimeLimitExceeded,
+            Some(Verdict::RuntimeError) => ProblemVerdict::RuntimeError,
+            Some(Verdict::InternalError) => ProblemVerdict::InternalError,
+        }
+    }
+
+    /// 順位表で不正解として数えるかどうか
+    pub fn is_rejected(&self) -> bool {
+        !matches!(self, ProblemVerdict::Accepted | ProblemVerdict::CompileError | ProblemVerdict::InternalError)
+    }
+}
+
+impl Problem {
+    /// 問題を読み込み、返したガードを持っている間はテストデータを更新させない
+    pub fn lock(id: &str) -> Result<(Self, RwLockReadGuard<'static, ()>), Box<dyn Error>> {
+        if !valid_problem_id(id) {
+            return Err(format!("invalid problem id: {id}").into());
+        }
+        let lock = test_data_lock(id).read().unwrap();
+        Ok((Self::read(id)?, lock))
+    }
+
+    /// 問題を読み込む。テストデータの入れ替えの途中を読まないように、読む間だけロックを持つ
+    pub fn load(id: &str) -> Result<Self, Box<dyn Error>> {
+        Ok(Self::lock(id)?.0)
+    }
+
+    fn read(id: &str) -> Result<Self, Box<dyn Error>> {
+        let dir = problem_dir(id);
+        let manifest_path = dir.join("problem.toml");
+        let manifest = read_to_string(&manifest_path).map_err(|err| format!("{}: {err}", manifest_path.display()) )?;
+        let manifest = toml::from_str::<ProblemManifest>(&manifest).map_err(|err| format!("{}: {err}", manifest_path.display()) )?;
+
+        let tests_dir = dir.join("tests");
+        let mut tests = vec![];
+        for entry in read_dir(&tests_dir).map_err(|err| format!("{}: {err}", tests_dir.display()) )? {
+            let path = entry?.path();
+            if path.extension().is_some_and(|extension| extension == "in" ) {
+                if !path.with_extension("out").is_file() {
+                    return Err(format!("{} has no matching .out file", path.display()).into());
+                }
+                tests.push(path.file_stem().unwrap_or_default().to_string_lossy().to_string());
+            }
+        }
+        if tests.is_empty() {
+            return Err(format!("{} has no test cases", tests_dir.display()).into());
+        }
+        tests.sort();
+
+        for subtask in &manifest.subtasks {
+            if !tests.iter().any(|test| subtask.contains(test) ) {
+                return Err(format!("{}: subtask {} has no test cases", manifest_path.display(), subtask.name).into());
+            }
+        }
+
+        let test_data_version = read_test_data(id)?.version;
+        Ok(Self { id: id.to_string(), manifest, tests, test_data_version })
+    }
+
+    pub fn read_input(&self, test: usize) -> io::Result<String> {
+        read_to_string(self.test_path(test, "in"))
+    }
+
+    pub fn read_output(&self, test: usize) -> io::Result<String> {
+        read_to_string(self.test_path(test, "out"))
+    }
+
+    fn test_path(&self, test: usize, extension: &str) -> PathBuf {
+        problem_dir(&self.id).join("tests").join(format!("{}.{extension}", self.tests[test]))
+    }
+
+    /// 満点
+    pub fn full_score(&self) -> u64 {
+        if self.manifest.subtasks.is_empty() {
+            self.manifest.score.unwrap_or(100)
+        } else {
+            self.manifest.subtasks.iter().map(|subtask| subtask.score ).sum()
+        }
+    }
+
+    /// テストケースごとの判定から得点を計算する
+    pub fn score(&self, verdicts: &[Verdict]) -> u64 {
+        if self.manifest.subtasks.is_empty() {
+            return if ProblemVerdict::of(verdicts) == ProblemVerdict::Accepted { self.full_score() } else { 0 };
+        }
+        self.manifest.subtasks.iter()
+            .filter(|subtask| self.tests.iter().zip(verdicts).all(|(test, &verdict)| !subtask.contains(test) || verdict == Verdict::Ok ) )
+            .map(|subtask| subtask.score )
+            .sum()
+    }
+}
+
+impl Subtask {
+    pub fn contains(&self, test: &str) -> bool {
+        self.tests.iter().any(|pattern| wildcard_match(pattern, test) )
+    }
+}
+
+pub fn problem_dir(id: &str) -> PathBuf {
+    PathBuf::from(&CONFIG.problem.dir).join(id)
+}
+
+/// 問題の `testdata.json` を読む。なければ版 0 の空のテストデータとする
+pub fn read_test_data(id: &str) -> Result<TestData, Box<dyn Error>> {
+    let path = problem_dir(id).join("testdata.json");
+    match read(&path) {
+        Ok(test_data) => Ok(serde_json::from_slice(&test_data).map_err(|err| format!("{}: {err}", path.display()) )?),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(TestData::default()),
+        Err(err) => Err(format!("{}: {err}", path.display()).into()),
+    }
+}
+
+/// 問題を作成するか、`problem.toml` を置き換える
+///
+/// 検証器があれば、今あるテストケースの入力をすべて検証する。
+pub fn save_manifest(id: &str, manifest: &ProblemManifest) -> Result<(), Box<dyn Error>> {
+    if !valid_problem_id(id) {
+        return Err(TestDataError(format!("invalid problem id: {id}")).into());
+    }
+    validate_solutions(&manifest.solutions)?;
+    validate_generators(manifest)?;
+    let dir = problem_dir(id);
+    create_dir_all(dir.join("tests"))?;
+    let validator = Validator::compile(id, manifest)?;
+    let _lock = test_data_lock(id).write().unwrap();
+    // テストケースがあれば、新しい小課題もそれで判定できなければならない
+    let tests = list_tests(&dir.join("tests"))?;
+    if !tests.is_empty() {
+        check_subtasks(manifest, &tests)?;
+    }
+    if let Some(validator) = &validator {
+        for test in &tests {
+            let input = read_to_string(dir.join("tests").join(format!("{test}.in")))?;
+            validator.validate(manifest, test, &input)?;
+        }
+    }
+    write_atomically(&dir.join("problem.toml"), toml::to_string(manifest)?.as_bytes())?;
+    Ok(())
+}
+
+/// テストデータを `files`（ファイル名から内容への対応）で置き換え、新しい版を返す
+///
+/// すべてのファイルを検証してから、テストケースのディレクトリをまとめて差し替える。
+/// 出力を主な解から作る問題では、`.out` を省略できる。
+pub fn replace_tests(id: &str, files: BTreeMap<String, Vec<u8>>) -> Result<TestData, Box<dyn Error>> {
+    let (manifest, validator, _lock) = write_lock_with_validator(id)?;
+
+    for (file_name, content) in &files {
+        validate_test_file(file_name, content)?;
+    }
+    for file_name in files.keys() {
+        let (name, extension) = file_name.rsplit_once('.').unwrap();
+        let pair = if extension == "in" { "out" } else { "in" };
+        let optional = pair == "out" && manifest.generates_outputs();
+        if !optional && !files.contains_key(&format!("{name}.{pair}")) {
+            return Err(TestDataError(format!("{file_name} has no matching .{pair} file")).into());
+        }
+    }
+    let tests = files.keys().filter_map(|file_name| Some(file_name.strip_suffix(".in")?.to_string()) ).collect::<Vec<_>>();
+    if tests.is_empty() {
+        return Err(TestDataError("no test cases found".to_string()).into());
+    }
+    if tests.len() > CONFIG.problem.max_test_count {
+        return Err(TestDataError(format!("at most {} test cases are allowed, but got {}", CONFIG.problem.max_test_count, tests.len())).into());
+    }
+    check_subtasks(&manifest, &tests)?;
+    if let Some(validator) = validator {
+        for test in &tests {
+            validator.validate(&manifest, test, std::str::from_utf8(&files[&format!("{test}.in")])?)?;
+        }
+    }
+
+    let dir = problem_dir(id);
+    let new_dir = dir.join(format!("tests.new-{}", Uuid::new_v4().simple()));
+    let old_dir = dir.join(format!("tests.old-{}", Uuid::new_v4().simple()));
+    create_dir_all(&new_dir)?;
+    let written = files.iter().try_for_each(|(file_name, content)| write(new_dir.join(file_name), content) );
+    if let Err(err) = written {
+        let _ = remove_dir_all(&new_dir);
+        return Err(err.into());
+    }
+    if dir.join("tests").exists() {
+        rename(dir.join("tests"), &old_dir)?;
+    }
+    rename(&new_dir, dir.join("tests"))?;
+    if old_dir.exists() {
+        remove_dir_all(&old_dir)?;
+    }
+
+    update_test_data(id, Some(vec![]))
+}
+
+/// テストケースのファイルを 1 つ追加または置き換え、新しい版を返す
+///
+/// 対になる `.out` がない `.in` はジャッジできないので、新しいテストケースは `.out` から追加する。
+/// 出力を主な解から作る問題では、`.in` だけを追加できる。
+pub fn put_test_file(id: &str, file_name: &str, content: &[u8]) -> Result<TestData, Box<dyn Error>> {
+    validate_test_file(file_name, content)?;
+    let (manifest, validator, _lock) = if file_name.ends_with(".in") {
+        write_lock_with_validator(id)?
+    } else {
+        let lock = test_data_lock(id).write().unwrap();
+        (read_manifest(id)?, None, lock)
+    };
+    if let (Some(test), Some(validator)) = (file_name.strip_suffix(".in"), validator) {
+        validator.validate(&manifest, test, std::str::from_utf8(content)?)?;
+    }
+
+    let tests_dir = problem_dir(id).join("tests");
+    create_dir_all(&tests_dir)?;
+    let name = file_name.rsplit_once('.').unwrap().0;
+    if read_test_data(id)?.generated.iter().any(|test| test == name ) {
+        return Err(TestDataError(format!("test case {name} is generated by the script; change the script instead")).into());
+    }
+    if let Some(name) = file_name.strip_suffix(".in") {
+        if !manifest.generates_outputs() && !tests_dir.join(format!("{name}.out")).is_file() {
+            return Err(TestDataError(format!("{name}.out does not exist; upload it before {file_name}")).into());
+        }
+        if !tests_dir.join(file_name).exists() && list_tests(&tests_dir)?.len() >= CONFIG.problem.max_test_count {
+            return Err(TestDataError(format!("at most {} test cases are allowed", CONFIG.problem.max_test_count)).into());
+        }
+    }
+    write_atomically(&tests_dir.join(file_name), content)?;
+    update_test_data(id, None)
+}
+
+/// テストケースを削除し、新しい版を返す
+pub fn delete_test(id: &str, name: &str) -> Result<TestData, Box<dyn Error>> {
+    let _lock = test_data_lock(id).write().unwrap();
+    let manifest = read_manifest(id)?;
+    if !valid_test_name(name) {
+        return Err(TestDataError(format!("invalid test case name: {name}")).into());
+    }
+    let tests_dir = problem_dir(id).join("tests");
+    let remaining = list_tests(&tests_dir)?.into_iter().filter(|test| test != name ).collect::<Vec<_>>();
+    if remaining.is_empty() {
+        return Err(TestDataError("cannot delete the last test case".to_string()).into());
+    }
+    check_subtasks(&manifest, &remaining)?;
+    let mut found = false;
+    for extension in ["in", "out"] {
+        match remove_file(tests_dir.join(format!("{name}.{extension}"))) {
+            Ok(()) => found = true,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+            Err(err) => return Err(err.into()),
+        }
+    }
+    if !found {
+        return Err(TestDataError(format!("test case {name} is not found")).into());
+    }
+    update_test_data(id, None)
+}
+
+/// 検証器などのファイルを問題のディレクトリに置く
+pub fn put_file(id: &str, name: &str, content: &[u8]) -> Result<(), Box<dyn Error>> {
+    let path = problem_file_path(id, name)?;
+    let _lock = test_data_lock(id).write().unwrap();
+    read_manifest(id)?;
+    create_dir_all(problem_dir(id).join("files"))?;
+    write_atomically(&path, content)?;
+    Ok(())
+}
+
+/// 問題のディレクトリに置いたファイルを削除する。`problem.toml` から参照されているファイルは削除できない
+pub fn delete_file(id: &str, name: &str) -> Result<(), Box<dyn Error>> {
+    let path = problem_file_path(id, name)?;
+    let _lock = test_data_lock(id).write().unwrap();
+    let manifest = read_manifest(id)?;
+    if manifest.validator.as_ref().is_some_and(|validator| validator.source == name ) {
+        return Err(TestDataError(format!("{name} is used as the validator")).into());
+    }
+    if manifest.solutions.iter().any(|solution| solution.source == name ) {
+        return Err(TestDataError(format!("{name} is used as a solution")).into());
+    }
+    if manifest.generators.iter().any(|generator| generator.source == name ) || manifest.script.as_deref() == Some(name) {
+        return Err(TestDataError(format!("{name} is used to generate the test cases")).into());
+    }
+    remove_file(&path)?;
+    Ok(())
+}
+
+/// 問題のディレクトリに置いたファイルのパス
+pub fn problem_file_path(id: &str, name: &str) -> Result<PathBuf, TestDataError> {
+    if !valid_file_name(name) {
+        return Err(TestDataError(format!("invalid file name: {name}")));
+    }
+    Ok(problem_dir(id).join("files").join(name))
+}
+
+/// 問題のディレクトリに置くファイルの名前には、問題 ID と同じ文字と `.` を使える
+pub fn valid_file_name(name: &str) -> bool {
+    !name.starts_with('.') && valid_problem_id(&name.replace('.', "_"))
+}
+
+/// アップロードされたテストケースの名前と入力。`.out` がないテストケースも含み、生成したテストケースは含まない
+pub fn read_inputs(id: &str) -> Result<Vec<(String, String)>, Box<dyn Error>> {
+    let _lock = test_data_lock(id).read().unwrap();
+    let generated = read_test_data(id)?.generated;
+    let tests_dir = problem_dir(id).join("tests");
+    let mut inputs = vec![];
+    for test in list_tests(&tests_dir)? {
+        if !generated.contains(&test) {
+            let input = read_to_string(tests_dir.join(format!("{test}.in")))?;
+            inputs.push((test, input));
+        }
+    }
+    Ok(inputs)
+}
+
+/// 問題の準備で生成した入力と出力を書き込み、新しい版を返す
+///
+/// `uploaded` は準備を始めたときに読んだアップロードされた入力で、その後に変わっていれば何も書き込まない。
+/// `outputs` は `uploaded` と `generated` をこの順に並べた入力に対する出力。前回生成して今回生成しなかったテストケースは削除する。
+pub fn write_prepared(id: &str, uploaded: &[(String, String)], generated: &[(String, String)], outputs: &[String]) -> Result<TestData, Box<dyn Error>> {
+    let _lock = test_data_lock(id).write().unwrap();
+    let tests_dir = problem_dir(id).join("tests");
+    let previous = read_test_data(id)?.generated;
+    let tests = list_tests(&tests_dir)?.into_iter().filter(|test| !previous.contains(test) ).collect::<Vec<_>>();
+    let unchanged = tests.len() == uploaded.len() && uploaded.iter().all(|(test, input)| read(tests_dir.join(format!("{test}.in"))).is_ok_and(|current| current == input.as_bytes() ) );
+    if !unchanged {
+        return Err(TestDataError("the test inputs changed while preparing the problem".to_string()).into());
+    }
+
+    for test in previous.iter().filter(|test| !generated.iter().any(|(name, _)| name == *test ) ) {
+        for extension in ["in", "out"] {
+            match remove_file(tests_dir.join(format!("{test}.{extension}"))) {
+                Err(err) if err.kind() != io::ErrorKind::NotFound => return Err(err.into()),
+                _ => {}
+            }
+        }
+    }
+    for (test, input) in generated {
+        write_atomically(&tests_dir.join(format!("{test}.in")), input.as_bytes())?;
+    }
+    for ((test, _), output) in uploaded.iter().chain(generated).zip(outputs) {
+        write_atomically(&tests_dir.join(format!("{test}.out")), output.as_bytes())?;
+    }
+    update_test_data(id, Some(generated.iter().map(|(test, _)| test.clone() ).collect()))
+}
+
+/// 問題の `problem.toml` を読む
+pub fn read_manifest(id: &str) -> Result<ProblemManifest, Box<dyn Error>> {
+    if !valid_problem_id(id) {
+        return Err(TestDataError(format!("invalid problem id: {id}")).into());
+    }
+    let path = problem_dir(id).join("problem.toml");
+    let manifest = match read_to_string(&path) {
+        Ok(manifest) => manifest,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Err(TestDataError(format!("problem {id} is not found")).into()),
+        Err(err) => return Err(format!("{}: {err}", path.display()).into()),
+    };
+    Ok(toml::from_str(&manifest).map_err(|err| format!("{}: {err}", path.display()) )?)
+}
+
+/// テストケースのファイル名は `<名前>.in` か `<名前>.out` で、内容は UTF-8 でなければならない
+fn validate_test_file(file_name: &str, content: &[u8]) -> Result<(), TestDataError> {
+    let valid_name = file_name.rsplit_once('.').is_some_and(|(name, extension)| valid_test_name(name) && (extension == "in" || extension == "out") );
+    if !valid_name {
+        return Err(TestDataError(format!("invalid file name: {file_name} (expected <name>.in or <name>.out)")));
+    }
+    if content.len() > CONFIG.problem.max_test_bytes {
+        return Err(TestDataError(format!("{file_name} must be at most {} bytes, but got {} bytes", CONFIG.problem.max_test_bytes, content.len())));
+    }
+    if std::str::from_utf8(content).is_err() {
+        return Err(TestDataError(format!("{file_name} is not valid UTF-8")));
+    }
+    Ok(())
+}
+
+/// テストケースの名前には問題 ID と同じ文字を使える
+fn valid_test_name(name: &str) -> bool {
+    valid_problem_id(name)
+}
+
+/// `.in` のファイルがあるテストケースの名前
+fn list_tests(tests_dir: &Path) -> io::Result<Vec<String>> {
+    let mut tests = vec![];
+    for entry in read_dir(tests_dir)? {
+        let path = entry?.path();
+        if path.extension().is_some_and(|extension| extension == "in" ) {
+            tests.push(path.file_stem().unwrap_or_default().to_string_lossy().to_string());
+        }
+    }
+    tests.sort();
+    Ok(tests)
+}
+
+pub fn check_subtasks(manifest: &ProblemManifest, tests: &[String]) -> Result<(), TestDataError> {
+    for subtask in &manifest.subtasks {
+        if !tests.iter().any(|test| subtask.contains(test) ) {
+            return Err(TestDataError(format!("subtask {} has no test cases", subtask.name)));
+        }
+    }
+    Ok(())
+}
+
+/// テストケースのチェックサムを計算し直し、版を上げて `testdata.json` に保存する
+///
+/// 対になるファイルがそろっていないテストケースは含めない。
+/// `generated` は生成したテストケースの名前で、`None` なら今ある生成したテストケースをそのまま引き継ぐ。
+fn update_test_data(id: &str, generated: Option<Vec<String>>) -> Result<TestData, Box<dyn Error>> {
+    let tests_dir = problem_dir(id).join("tests");
+    let mut tests = vec![];
+    for name in list_tests(&tests_dir)? {
+        let output_path = tests_dir.join(format!("{name}.out"));
+        if output_path.is_file() {
+            let input = file_info(&tests_dir.join(format!("{name}.in")))?;
+            tests.push(TestCaseInfo { name, input, output: file_info(&output_path)? });
+        }
+    }
+
+    let previous = read_test_data(id)?;
+    let generated = generated.unwrap_or(previous.generated).into_iter().filter(|test| tests_dir.join(format!("{test}.in")).is_file() ).collect();
+    let test_data = TestData {
+        version: previous.version + 1,
+        updated_at: unix_millis(SystemTime::now()),
+        tests,
+        generated,
+    };
+    write_atomically(&problem_dir(id).join("testdata.json"), &serde_json::to_vec_pretty(&test_data)?)?;
+    Ok(test_data)
+}
+
+fn file_info(path: &Path) -> io::Result<FileInfo> {
+    let content = read(path)?;
+    let sha256 = digest(&SHA256, &content).as_ref().iter().map(|byte| format!("{byte:02x}") ).collect();
+    Ok(FileInfo { bytes: content.len() as u64, sha256 })
+}
+
+/// 一時ファイルに書いてから名前を変え、書きかけのファイルが読まれないようにする
+pub fn write_atomically(path: &Path, content: &[u8]) -> io::Result<()> {
+    let temporary = path.with_extension(format!("tmp-{}", Uuid::new_v4().simple()));
+    write(&temporary, content)?;
+    rename(&temporary, path)
+}
+
+/// 空白で区切った単語の列として出力を比較する
+pub fn check_output(output: &str, expected: &str) -> bool {
+    output.split_ascii_whitespace().eq(expected.split_ascii_whitespace())
+}
+
+fn wildcard_match(pattern: &str, text: &str) -> bool {
+    match pattern.split_once('*') {
+        None => pattern == text,
+        Some((prefix, rest)) => text.strip_prefix(prefix).is_some_and(|text| (0 ..= text.len()).any(|i| text.is_char_boundary(i) && wildcard_match(rest, &text[i ..]) ) ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wildcard_matches_any_substring() {
+        assert!(wildcard_match("sample_1", "sample_1"));
+        assert!(!wildcard_match("sample_1", "sample_10"));
+        assert!(wildcard_match("sample_*", "sample_"));
+        assert!(wildcard_match("sample_*", "sample_10"));
+        assert!(!wildcard_match("sample_*", "random_1"));
+        assert!(wildcard_match("*_max", "random_max"));
+        assert!(!wildcard_match("*_max", "random_max_2"));
+        assert!(wildcard_match("*_*_*", "a_b_c"));
+        assert!(wildcard_match("a*b*c", "abbbc"));
+        assert!(!wildcard_match("a*b*c", "acb"));
+        assert!(wildcard_match("*", ""));
+        assert!(wildcard_match("テスト*", "テスト_1"));
+    }
+}
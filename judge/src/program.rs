@@ -1,14 +1,87 @@
 pub mod compile;
 pub mod execute;
+pub mod project;
 
 use crate::config::*;
 
+use std::collections::BTreeMap;
 use std::path::*;
 use std::fs::*;
 use std::error::*;
+use std::fmt;
+use std::os::unix::process::ExitStatusExt;
+use std::process::ExitStatus;
+
+use nix::sys::signal::Signal;
+use serde::{Deserialize, Serialize};
+use tracing::{debug, warn};
 
 use self::compile::*;
 use self::execute::*;
+use self::project::*;
+
+/// 提出されたソースコード
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Source {
+    /// 単一の main.rs
+    Single(String),
+    /// パスから内容への対応で表したファイルツリー
+    Files(BTreeMap<String, String>),
+}
+
+/// 提出されたソースコードに起因するエラー
+///
+/// `Program::new` がこれ以外のエラーを返した場合は、ジャッジ側の問題として扱う。
+#[derive(Debug)]
+pub struct SourceError(pub String);
+
+impl fmt::Display for SourceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl Error for SourceError {}
+
+impl From<&str> for SourceError {
+    fn from(message: &str) -> Self {
+        Self(message.to_string())
+    }
+}
+
+impl From<String> for SourceError {
+    fn from(message: String) -> Self {
+        Self(message)
+    }
+}
+
+/// プロセスの終了状態
+///
+/// 正常に終了した場合は `exit_code` が、シグナルで終了した場合は `signal` と `signal_name` が設定される。
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct ExitInfo {
+    pub exit_code: Option<i32>,
+    pub signal: Option<i32>,
+    pub signal_name: Option<String>,
+}
+
+impl ExitInfo {
+    pub fn success(&self) -> bool {
+        self.exit_code == Some(0)
+    }
+}
+
+impl From<ExitStatus> for ExitInfo {
+    fn from(status: ExitStatus) -> Self {
+        let signal = status.signal();
+        Self {
+            exit_code: status.code(),
+            signal,
+            signal_name: signal.and_then(|signal| Signal::try_from(signal).ok() ).map(|signal| signal.as_str().to_string() ),
+        }
+    }
+}
 
 pub struct Program {
     submission_id: String,
@@ -16,27 +89,51 @@ pub struct Program {
 }
 
 impl Program {
-    pub fn new(submission_id: &str, source_code: &str) -> Result<Self, Box<dyn Error>> {
+    pub fn new(submission_id: &str, source: &Source, profile: &CompileProfile) -> Result<Self, Box<dyn Error>> {
         // 実行用ディレクトリを作成
         let exec_dir = PathBuf::from(CONFIG.program.execute_dir.replace("{submission_id}", submission_id));
         create_dir_all(&exec_dir)?;
-        create_dir_all(&exec_dir.join("lib"))?;
-        create_dir_all(&exec_dir.join("lib64"))?;
+        create_dir_all(exec_dir.join("lib"))?;
+        create_dir_all(exec_dir.join("lib64"))?;
+
+        let compile_result = match source {
+            Source::Single(source_code) => {
+                // ソースコードを保存
+                save_source(submission_id, source_code.as_bytes())?;
+
+                // コンパイル
+                let compile_result = compile(submission_id, profile);
+
+                // ソースコードを削除
+                remove_file(CONFIG.program.source_path.replace("{submission_id}", submission_id))?;
 
-        // ソースコードを保存
-        save_source(submission_id, source_code.as_bytes())?;
+                compile_result?
+            }
+            Source::Files(files) => {
+                // ファイルツリーを保存
+                let kind = save_project(submission_id, files, profile);
 
-        // コンパイル
-        let compile_result = compile(submission_id)?;
+                // ビルド
+                let compile_result = kind.and_then(|kind| build_project(submission_id, &kind, profile) );
 
-        // ソースコードを削除
-        remove_file(&CONFIG.program.source_path.replace("{submission_id}", submission_id))?;
+                // ファイルツリーを削除
+                if let Err(err) = remove_dir_all(project_dir(submission_id)) {
+                    warn!(%err, "failed to remove the project directory");
+                }
+
+                compile_result?
+            }
+        };
 
         Ok(Self { compile_result, submission_id: submission_id.to_string() })
     }
 
-    pub fn run(&self, input: &str) -> Result<execute::ExecutionResult, Box<dyn Error>> {
-        execute(&self.submission_id, input)
+    pub fn run(&self, input: &str, kill_handle: &KillHandle) -> Result<execute::ExecutionResult, Box<dyn Error>> {
+        self.run_with_args(input, &[], kill_handle)
+    }
+
+    pub fn run_with_args(&self, input: &str, args: &[String], kill_handle: &KillHandle) -> Result<execute::ExecutionResult, Box<dyn Error>> {
+        execute(&self.submission_id, input, args, kill_handle)
     }
 
     pub fn compile_result(&self) -> &CompilingResult {
@@ -49,8 +146,14 @@ impl Drop for Program {
         let exec_dir = PathBuf::from(CONFIG.program.execute_dir.replace("{submission_id}", submission_id));
 
         // 実行用ディレクトリを削除
-        let _ = remove_dir(&exec_dir.join("lib64"));
-        let _ = remove_dir(&exec_dir.join("lib"));
-        let _ = remove_dir_all(&exec_dir);
+        for mount_point in ["lib64", "lib"] {
+            if let Err(err) = remove_dir(exec_dir.join(mount_point)) {
+                warn!(%err, mount_point, "failed to remove the mount point");
+            }
+        }
+        match remove_dir_all(&exec_dir) {
+            Ok(()) => debug!("removed the execution directory"),
+            Err(err) => warn!(%err, "failed to remove the execution directory"),
+        }
     }
 }
\ No newline at end of file
@@ -1,8 +1,8 @@
 use crate::config::*;
-use crate::program::*;
+
+use crate::program::ExitInfo;
 
 use serde::*;
-use std::os::unix::process::ExitStatusExt;
 use std::process::*;
 use std::fs::*;
 use std::io::Write;
@@ -10,11 +10,24 @@ use std::error::*;
 
 #[derive(Clone, Debug, Serialize)]
 pub struct CompilingResult {
-    pub status: i32,
+    #[serde(flatten)]
+    pub exit: ExitInfo,
     pub stdout: String,
     pub stderr: String,
 }
 
+impl CompilingResult {
+    /// rustc の JSON 形式の診断から、エラーの表示用の文字列を取り出す
+    pub fn error_message(&self) -> String {
+        let rendered = self.stderr.lines()
+            .filter_map(|line| serde_json::from_str::<serde_json::Value>(line).ok() )
+            .filter(|diagnostic| diagnostic["level"] == "error" )
+            .filter_map(|diagnostic| Some(diagnostic["rendered"].as_str()?.trim_end().to_string()) )
+            .collect::<Vec<_>>();
+        if rendered.is_empty() { self.stderr.trim().to_string() } else { rendered.join("\n") }
+    }
+}
+
 pub fn save_source(submission_id: &str, source_code: &[u8]) -> Result<(), Box<dyn Error>> {
     let mut source_file = File::create(CONFIG.program.source_path.replace("{submission_id}", submission_id))?;
     source_file.write_all(source_code)?;
@@ -22,43 +35,64 @@ pub fn save_source(submission_id: &str, source_code: &[u8]) -> Result<(), Box<dy
     Ok(())
 }
 
-pub fn compile(submission_id: &str) -> Result<CompilingResult, Box<dyn Error>> {
+pub fn compile(submission_id: &str, profile: &CompileProfile) -> Result<CompilingResult, Box<dyn Error>> {
+    compile_file(submission_id, &CONFIG.program.source_path.replace("{submission_id}", submission_id), profile)
+}
+
+/// `source_path` をクレートルートとしてコンパイルする
+pub fn compile_file(submission_id: &str, source_path: &str, profile: &CompileProfile) -> Result<CompilingResult, Box<dyn Error>> {
     let output = Command::new("rustc")
-        .args(&compile_args(submission_id))
+        .args(profile.toolchain.as_ref().map(|toolchain| format!("+{toolchain}") ))
+        .args(compile_args(submission_id, source_path, profile))
         .stdout(Stdio::piped())
         .stderr(Stdio::piped())
         .output()?;
 
-    let status = output.status.into_raw();
+    let exit = ExitInfo::from(output.status);
     let stdout = String::from_utf8(output.stdout)?;
     let stderr = String::from_utf8(output.stderr)?;
 
-    Ok(CompilingResult { status, stdout, stderr })
+    Ok(CompilingResult { exit, stdout, stderr })
 }
 
-fn compile_args(submission_id: &str) -> Vec<String> {
-    let source_path = CONFIG.program.source_path.replace("{submission_id}", submission_id);
-    let opt_dependency = format!("dependency={}", CONFIG.program.dependency_dir.replace("{submission_id}", submission_id));
-    let codegen_opts = ["opt-level=3", "embed-bitcode=no"];
-    
+/// 名前からコンパイルプロファイルを探す
+pub fn find_profile(name: &str) -> Result<&'static CompileProfile, Box<dyn Error>> {
+    CONFIG.program.profiles.get(name).ok_or_else(|| format!("unknown compile profile: {name}").into() )
+}
+
+/// プロファイルによって決まる、ソースファイル以外に関する rustc の引数
+pub fn profile_args(profile: &CompileProfile) -> Vec<String> {
     let mut args = vec![];
-    args.push("--crate-name=main".to_string());
-    args.push("--edition=2018".to_string());
-    args.push("--error-format=json".to_string());
-    args.push("--json=diagnostic-short".to_string());
-    args.push("--crate-type=bin".to_string());
-    args.push("--emit=link".to_string());
-    for codegen_opt in &codegen_opts {
+    for codegen_opt in &profile.codegen_options {
         args.push("-C".to_string());
         args.push(codegen_opt.to_string());
     }
-    for (name, path) in &CONFIG.program.externs {
-        args.push("--extern".to_string());
-        args.push(format!("{name}={path}"));
+    args.extend(profile.flags.iter().cloned());
+    args
+}
+
+fn compile_args(submission_id: &str, source_path: &str, profile: &CompileProfile) -> Vec<String> {
+    let opt_dependency = format!("dependency={}", CONFIG.program.dependency_dir.replace("{submission_id}", submission_id));
+
+    let mut args = vec![
+        "--crate-name=main".to_string(),
+        "--edition=2018".to_string(),
+        "--error-format=json".to_string(),
+        "--json=diagnostic-short".to_string(),
+        "--crate-type=bin".to_string(),
+        "--emit=link".to_string(),
+    ];
+    args.extend(profile_args(profile));
+    if profile.externs {
+        for (name, path) in &CONFIG.program.externs {
+            let path = path.as_str().unwrap_or_default();
+            args.push("--extern".to_string());
+            args.push(format!("{name}={path}"));
+        }
+        args.push("-L".to_string());
+        args.push(opt_dependency);
     }
     args.push(format!("--out-dir={}", CONFIG.program.execute_dir.replace("{submission_id}", submission_id)));
-    args.push("-L".to_string());
-    args.push(opt_dependency);
-    args.push(source_path);
+    args.push(source_path.to_string());
     args
 }
\ No newline at end of file
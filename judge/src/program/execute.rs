@@ -1,67 +1,197 @@
+/// 1 つの入力に対する実行結果の判定
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Verdict {
+    /// 正常に終了した
+    Ok,
+    /// 正常に終了したが、出力が正しくない（問題への提出のみ）
+    WrongAnswer,
+    /// 0 以外の終了コードで終了したか、シグナルで終了した
+    RuntimeError,
+    /// 実行時間制限を超えて強制終了された
+    TimeLimitExceeded,
+    /// ジャッジ側の問題により実行できなかった
+    InternalError,
+}
+
 #[derive(Clone, Debug, Serialize)]
 pub struct ExecutionResult {
-    pub status: i32,
+    pub verdict: Verdict,
+    #[serde(flatten)]
+    pub exit: ExitInfo,
     pub time_ms: i32,
     pub stdout: String,
     pub stderr: String,
+    /// `InternalError` の原因
+    pub error: Option<String>,
+}
+
+impl ExecutionResult {
+    pub fn internal_error(message: String) -> Self {
+        Self {
+            verdict: Verdict::InternalError,
+            exit: ExitInfo::default(),
+            time_ms: 0,
+            stdout: "".to_string(),
+            stderr: "".to_string(),
+            error: Some(message),
+        }
+    }
 }
 
-pub fn execute(submission_id: &str, input: &str) -> Result<ExecutionResult, Box<dyn Error>> {
+/// 実行中のプロセスを他のスレッドから強制終了するためのハンドル
+///
+/// 一度 `kill` すると、以降にこのハンドルで起動したプロセスも直ちに強制終了される。
+#[derive(Clone, Debug, Default)]
+pub struct KillHandle(Arc<Mutex<KillState>>);
+
+#[derive(Debug, Default)]
+struct KillState {
+    pid: Option<u32>,
+    killed: bool,
+}
+
+impl KillHandle {
+    pub fn kill(&self) {
+        let mut state = self.0.lock().unwrap();
+        state.killed = true;
+        if let Some(pid) = state.pid {
+            kill_process_group(pid);
+        }
+    }
+
+    pub fn is_killed(&self) -> bool {
+        self.0.lock().unwrap().killed
+    }
+
+    fn attach(&self, pid: u32) {
+        let mut state = self.0.lock().unwrap();
+        state.pid = Some(pid);
+        if state.killed {
+            kill_process_group(pid);
+        }
+    }
+
+    fn detach(&self) {
+        self.0.lock().unwrap().pid = None;
+    }
+}
+
+/// timeout は自身をプロセスグループのリーダーとして子プロセスを起動するため、グループごと強制終了する
+fn kill_process_group(pid: u32) {
+    let _ = killpg(Pid::from_raw(pid as i32), Signal::SIGKILL);
+}
+
+/// `args` はプログラムに渡すコマンドライン引数
+pub fn execute(submission_id: &str, input: &str, args: &[String], kill_handle: &KillHandle) -> Result<ExecutionResult, Box<dyn Error>> {
+    let base_path = PathBuf::from(CONFIG.program.execute_dir.replace("{submission_id}", submission_id));
+    let mut command = limited_command(CONFIG.program.time_limit);
+    command.args(["./safe_run", base_path.to_str().unwrap(), "main"]).args(args);
+    let (Output { status, stdout, stderr }, run_time) = run_limited(&mut command, input.as_bytes(), kill_handle)?;
+    METRICS.run_duration.observe(run_time.as_secs_f64());
+
+    // timeout は子プロセスを終了させたシグナルをそのまま自身に送るので、時間切れは SIGKILL による終了として観測される
+    let exit = ExitInfo::from(status);
+    let verdict = if exit.success() {
+        Verdict::Ok
+    } else if exited_by_timeout(&exit, run_time, CONFIG.program.time_limit) {
+        Verdict::TimeLimitExceeded
+    } else {
+        Verdict::RuntimeError
+    };
+    let stdout = String::from_utf8_lossy(&stdout).into_owned();
+    let stderr = String::from_utf8_lossy(&stderr).into_owned();
+
+    Ok(ExecutionResult {
+        verdict,
+        exit,
+        time_ms: run_time.as_millis() as i32,
+        stdout,
+        stderr,
+        error: None,
+    })
+}
+
+/// `time_limit` 秒で強制終了される `timeout` のコマンド。実行するコマンドを引数に追加して `run_limited` に渡す
+pub fn limited_command(time_limit: u64) -> Command {
+    let mut command = Command::new("timeout");
+    command.args(["-s9", &time_limit.to_string()]);
+    command
+}
+
+/// `time_limit` 秒の制限で実行したプロセスが、時間切れで強制終了されたかどうか
+pub fn exited_by_timeout(exit: &ExitInfo, run_time: time::Duration, time_limit: u64) -> bool {
+    exit.signal == Some(Signal::SIGKILL as i32) && run_time >= time::Duration::from_secs(time_limit)
+}
+
+/// `command` を起動して cgroup に追加し、`input` を標準入力に書き込んで終了を待つ。出力と経過時間を返す
+pub fn run_limited(command: &mut Command, input: &[u8], kill_handle: &KillHandle) -> Result<(Output, time::Duration), Box<dyn Error>> {
     // 計測開始
     let time_start = time::Instant::now();
 
     // 子プロセスを起動
-    let base_path = PathBuf::from(CONFIG.program.execute_dir.replace("{submission_id}", submission_id));
-    let mut process = Command::new("timeout")
-        .args(["-s9", &CONFIG.program.time_limit.to_string(), "./safe_run", base_path.to_str().unwrap(), "main"])
+    let mut process = command
         .stdin(Stdio::piped())
         .stdout(Stdio::piped())
         .stderr(Stdio::piped())
-        .spawn()?;
+        .spawn()
+        .inspect_err(|_| sandbox_failure("spawn") )?;
 
     // cgroup に追加
     let pid = process.id();
-    for cgroup_task_path in &CGROUP_TASKS_PATHS {
-        let mut tasks = OpenOptions::new().append(true).open(cgroup_task_path)?;
-        tasks.write(format!("{}\n", pid).as_bytes())?;
+    kill_handle.attach(pid);
+    let add_to_cgroup = || -> std::io::Result<()> {
+        for cgroup_task_path in &CGROUP_TASKS_PATHS {
+            let mut tasks = OpenOptions::new().append(true).open(cgroup_task_path)?;
+            tasks.write_all(format!("{}\n", pid).as_bytes())?;
+        }
+        Ok(())
+    };
+    if let Err(err) = add_to_cgroup() {
+        // 制限のかからないまま実行させない
+        let _ = process.kill();
+        let _ = process.wait();
+        kill_handle.detach();
+        sandbox_failure("cgroup");
+        return Err(format!("failed to add the process to cgroup {CGROUP_TASKS_PATHS:?}: {err}").into());
     }
 
     // 入力を書き込み
     if let Some(mut stdin) = process.stdin.take() {
-        stdin.write(input.as_bytes())?;
+        // 入力を読み切らずに終了した場合は書き込みに失敗するが、実行結果には影響しない
+        let _ = stdin.write_all(input);
         // drop により自動で stdin が閉じる
     }
 
     // 終了を待つ
-    let Output { status, stdout, stderr } = process.wait_with_output()?;
+    let output = process.wait_with_output();
+    kill_handle.detach();
+    let output = output.inspect_err(|_| sandbox_failure("wait") )?;
 
     // 計測終了
-    let run_time = time_start.elapsed();
-
-    let status = status.into_raw();
-    let stdout = String::from_utf8(stdout)?;
-    let stderr = String::from_utf8(stderr)?;
+    Ok((output, time_start.elapsed()))
+}
 
-    Ok(ExecutionResult {
-        status,
-        time_ms: run_time.as_millis() as i32,
-        stdout,
-        stderr,
-    })
+fn sandbox_failure(reason: &str) {
+    METRICS.sandbox_failures.with_label_values(&[reason]).inc();
 }
 
-const CGROUP_TASKS_PATHS: [&'static str; 1] = [
+pub const CGROUP_TASKS_PATHS: [&str; 1] = [
     "/sys/fs/cgroup/memory/judge/tasks",
 ];
 
 use std::io::Write;
-use std::os::unix::process::ExitStatusExt;
 use std::process::*;
 use std::time;
 use std::fs::*;
 use std::path::PathBuf;
 use std::error::*;
+use std::sync::{Arc, Mutex};
 
+use nix::sys::signal::{killpg, Signal};
+use nix::unistd::Pid;
 use serde::Serialize;
 
-use crate::config::*;
\ No newline at end of file
+use crate::config::*;
+use crate::metrics::METRICS;
+use crate::program::ExitInfo;
\ No newline at end of file
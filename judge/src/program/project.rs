@@ -0,0 +1,290 @@
+use crate::config::*;
+use crate::metrics::METRICS;
+use crate::program::compile::*;
+use crate::program::execute::{exited_by_timeout, limited_command, run_limited, KillHandle};
+use crate::program::{ExitInfo, SourceError};
+
+use std::collections::*;
+use std::error::*;
+use std::fs::*;
+use std::path::*;
+
+use serde_json::Value as JsonValue;
+use toml::{Table, Value};
+
+/// 複数ファイルからなる提出のビルド方法
+pub enum ProjectKind {
+    /// `src/main.rs` をクレートルートとして rustc でコンパイルする
+    Rustc,
+    /// `Cargo.toml` を含むため cargo でビルドする
+    Cargo { edition: String },
+}
+
+/// ファイルツリーを `project_dir` に保存する
+pub fn save_project(submission_id: &str, files: &BTreeMap<String, String>, profile: &CompileProfile) -> Result<ProjectKind, Box<dyn Error>> {
+    let project_dir = project_dir(submission_id);
+    for path in files.keys() {
+        validate_path(path)?;
+    }
+    if !files.contains_key("src/main.rs") {
+        return Err(SourceError::from("src/main.rs is required").into());
+    }
+
+    // `externs = false` のプロファイルでは依存クレートを使えない
+    let allowed = if profile.externs { allowed_dependencies()? } else { BTreeSet::new() };
+    let kind = match files.get("Cargo.toml") {
+        Some(manifest) => ProjectKind::Cargo { edition: validate_manifest(manifest, &allowed)? },
+        None => ProjectKind::Rustc,
+    };
+
+    // ビルド済みの依存クレートを提出ごとのターゲットディレクトリに複製する。
+    // 複製したファイルは更新時刻が新しくなるので、ソースより後に複製すると提出のクレートまで再ビルドされないことがある
+    if let ProjectKind::Cargo { .. } = &kind {
+        let dependency_release_dir = dependency_project_dir()?.join("target/release");
+        if dependency_release_dir.is_dir() {
+            copy_dir(&dependency_release_dir, &project_dir.join("target/release"))?;
+        }
+    }
+
+    for (path, content) in files {
+        if path == "Cargo.toml" || path == "Cargo.lock" {
+            continue;
+        }
+        let path = project_dir.join(path);
+        if let Some(parent) = path.parent() {
+            create_dir_all(parent)?;
+        }
+        write(path, content)?;
+    }
+
+    if let ProjectKind::Cargo { edition } = &kind {
+        let dependency_project_dir = dependency_project_dir()?;
+        let (manifest, lock) = (dependency_project_dir.join("Cargo.toml"), dependency_project_dir.join("Cargo.lock"));
+        if !manifest.is_file() || !lock.is_file() {
+            return Err(SourceError::from("cargo projects are unavailable because the dependencies are not built").into());
+        }
+
+        // パッケージ名と依存関係をビルド済みのものに揃え、Cargo.lock をそのまま使えるようにする
+        let mut manifest = read_to_string(manifest)?.parse::<Table>()?;
+        if let Some(package) = manifest.get_mut("package").and_then(Value::as_table_mut) {
+            package.insert("edition".to_string(), Value::from(edition.as_str()));
+            package.insert("build".to_string(), Value::from(false));
+            package.insert("autobins".to_string(), Value::from(false));
+            package.insert("autoexamples".to_string(), Value::from(false));
+            package.insert("autotests".to_string(), Value::from(false));
+            package.insert("autobenches".to_string(), Value::from(false));
+        }
+        let mut bin = Table::new();
+        bin.insert("name".to_string(), Value::from("main"));
+        bin.insert("path".to_string(), Value::from("src/main.rs"));
+        manifest.insert("bin".to_string(), Value::from(vec![Value::from(bin)]));
+        write(project_dir.join("Cargo.toml"), manifest.to_string())?;
+        copy(lock, project_dir.join("Cargo.lock"))?;
+
+        create_dir_all(project_dir.join(".cargo"))?;
+        let vendor_dir = dependency_project_dir.join("vendor");
+        write(project_dir.join(".cargo/config.toml"), format!("[source.crates-io]\nreplace-with = \"vendored-sources\"\n\n[source.vendored-sources]\ndirectory = {}\n", Value::from(vendor_dir.to_string_lossy().into_owned())))?;
+    }
+
+    Ok(kind)
+}
+
+/// 保存したファイルツリーをビルドし、実行ファイルを実行用ディレクトリに置く
+pub fn build_project(submission_id: &str, kind: &ProjectKind, profile: &CompileProfile) -> Result<CompilingResult, Box<dyn Error>> {
+    match kind {
+        ProjectKind::Rustc => {
+            let source_path = project_dir(submission_id).join("src/main.rs");
+            compile_file(submission_id, &source_path.to_string_lossy(), profile)
+        }
+        ProjectKind::Cargo { .. } => build_cargo(submission_id, profile),
+    }
+}
+
+/// 提出ごとのターゲットディレクトリで cargo を実行する
+///
+/// 提出の build.rs は `build = false` で無効にしてあり、依存クレートは `save_project` が複製したビルド済みのものを使う。
+/// cargo は実行時と同じく cgroup に入れ、`program.build_time_limit` 秒で強制終了する。
+fn build_cargo(submission_id: &str, profile: &CompileProfile) -> Result<CompilingResult, Box<dyn Error>> {
+    let project_dir = project_dir(submission_id);
+    let target_dir = absolute(project_dir.join("target"))?;
+    // プロファイルのオプションは cargo rustc で提出されたクレートにのみ適用する
+    let mut command = limited_command(CONFIG.program.build_time_limit);
+    command.arg("cargo")
+        .args(profile.toolchain.as_ref().map(|toolchain| format!("+{toolchain}") ))
+        .args(["rustc", "--release", "--offline", "--frozen", "--bin=main", "--message-format=json-diagnostic-short"])
+        .arg(format!("--target-dir={}", target_dir.to_string_lossy()))
+        .arg("--")
+        .args(profile_args(profile))
+        .current_dir(&project_dir);
+    let (output, build_time) = run_limited(&mut command, b"", &KillHandle::default())?;
+
+    // rustc を直接呼び出したときと同じく、診断を 1 行に 1 つの JSON として stderr に置く
+    let mut stderr = String::new();
+    for line in output.stdout.split(|&c| c == b'\n' ) {
+        let Ok(message) = serde_json::from_slice::<JsonValue>(line) else { continue };
+        if message["reason"] == "compiler-message" {
+            stderr.push_str(&message["message"].to_string());
+            stderr.push('\n');
+        }
+        // 提出のクレート以外は、複製したビルド済みの依存クレートを使えたかどうかを数える
+        let is_dependency = message["package_id"].as_str().is_some_and(|id| !id.starts_with("path+") );
+        if message["reason"] == "compiler-artifact" && is_dependency {
+            METRICS.cache_lookup("dependencies", message["fresh"] == true);
+        }
+    }
+    let exit = ExitInfo::from(output.status);
+    if exited_by_timeout(&exit, build_time, CONFIG.program.build_time_limit) {
+        stderr.push_str(&format!("the build exceeded the time limit of {} seconds\n", CONFIG.program.build_time_limit));
+    } else if !exit.success() {
+        stderr.push_str(&String::from_utf8_lossy(&output.stderr));
+    }
+
+    if exit.success() {
+        let exec_dir = PathBuf::from(CONFIG.program.execute_dir.replace("{submission_id}", submission_id));
+        copy(target_dir.join("release/main"), exec_dir.join("main"))?;
+    }
+
+    Ok(CompilingResult { exit, stdout: "".to_string(), stderr })
+}
+
+/// ディレクトリを再帰的に複製する。インクリメンタルコンパイルの中間生成物は複製しない
+fn copy_dir(from: &Path, to: &Path) -> Result<(), Box<dyn Error>> {
+    create_dir_all(to)?;
+    for entry in read_dir(from)? {
+        let entry = entry?;
+        let (path, file_type) = (entry.path(), entry.file_type()?);
+        if file_type.is_dir() {
+            if entry.file_name() != "incremental" {
+                copy_dir(&path, &to.join(entry.file_name()))?;
+            }
+        } else if file_type.is_file() {
+            copy(&path, to.join(entry.file_name()))?;
+        }
+    }
+    Ok(())
+}
+
+pub fn project_dir(submission_id: &str) -> PathBuf {
+    PathBuf::from(CONFIG.program.project_dir.replace("{submission_id}", submission_id))
+}
+
+/// build_deps が作成する、依存クレートをビルドするための Cargo プロジェクト
+fn dependency_project_dir() -> Result<PathBuf, Box<dyn Error>> {
+    Ok(absolute(PathBuf::from(&CONFIG.program.dependency_dir).join("build"))?)
+}
+
+fn validate_path(path: &str) -> Result<(), SourceError> {
+    let components = Path::new(path).components().collect::<Vec<_>>();
+    if components.is_empty() || !components.iter().all(|component| matches!(component, Component::Normal(_)) ) {
+        return Err(format!("invalid file path: {path}").into());
+    }
+    if components.len() == 1 && path != "Cargo.toml" && path != "Cargo.lock" {
+        return Err(format!("files other than Cargo.toml and Cargo.lock must be placed under src/: {path}").into());
+    }
+    if components.len() > 1 && components[0].as_os_str() != "src" {
+        return Err(format!("files other than Cargo.toml and Cargo.lock must be placed under src/: {path}").into());
+    }
+    Ok(())
+}
+
+/// 提出された Cargo.toml を検査し、edition を返す
+///
+/// 依存関係はビルド済みのクレートの名前のみを許可し、バージョンや features はビルド済みのものが使われる。
+fn validate_manifest(manifest: &str, allowed: &BTreeSet<String>) -> Result<String, SourceError> {
+    let manifest = manifest.parse::<Table>().map_err(|err| SourceError(format!("Cargo.toml: {err}")) )?;
+    for key in manifest.keys() {
+        if key != "package" && key != "dependencies" {
+            return Err(format!("Cargo.toml: [{key}] is not allowed").into());
+        }
+    }
+
+    let mut edition = "2018".to_string();
+    if let Some(package) = manifest.get("package") {
+        let package = package.as_table().ok_or("Cargo.toml: [package] must be a table")?;
+        for (key, value) in package {
+            match key.as_str() {
+                "name" | "version" | "authors" | "description" | "publish" => {}
+                "edition" => edition = value.as_str().ok_or("Cargo.toml: package.edition must be a string")?.to_string(),
+                _ => return Err(format!("Cargo.toml: package.{key} is not allowed").into()),
+            }
+        }
+        if !["2015", "2018", "2021"].contains(&edition.as_str()) {
+            return Err(format!("Cargo.toml: unsupported edition {edition}").into());
+        }
+    }
+
+    if let Some(dependencies) = manifest.get("dependencies") {
+        let dependencies = dependencies.as_table().ok_or("Cargo.toml: [dependencies] must be a table")?;
+        for name in dependencies.keys() {
+            if !allowed.contains(name) {
+                return Err(format!("Cargo.toml: dependency {name} is not available").into());
+            }
+        }
+    }
+
+    Ok(edition)
+}
+
+fn allowed_dependencies() -> Result<BTreeSet<String>, Box<dyn Error>> {
+    let manifest_path = dependency_project_dir()?.join("Cargo.toml");
+    if !manifest_path.is_file() {
+        return Ok(BTreeSet::new());
+    }
+    let manifest = read_to_string(manifest_path)?.parse::<Table>()?;
+    Ok(manifest.get("dependencies").and_then(Value::as_table).map(|dependencies| dependencies.keys().cloned().collect() ).unwrap_or_default())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn allowed(names: &[&str]) -> BTreeSet<String> {
+        names.iter().map(|name| name.to_string() ).collect()
+    }
+
+    fn manifest_error(manifest: &str) -> String {
+        validate_manifest(manifest, &allowed(&["itertools", "proconio"])).unwrap_err().to_string()
+    }
+
+    #[test]
+    fn paths_must_stay_under_src() {
+        for path in ["src/main.rs", "src/a/b.rs", "Cargo.toml", "Cargo.lock"] {
+            assert!(validate_path(path).is_ok(), "{path}");
+        }
+        for path in ["", "main.rs", "tests/a.rs", "/src/main.rs", "src/../main.rs", "./src/main.rs", "../src/main.rs"] {
+            assert!(validate_path(path).is_err(), "{path}");
+        }
+    }
+
+    #[test]
+    fn manifest_allows_package_metadata_and_prebuilt_dependencies() {
+        let manifest = "[package]\nname = \"a\"\nversion = \"0.1.0\"\nedition = \"2021\"\n\n[dependencies]\nitertools = \"0.10\"\nproconio = { version = \"*\", features = [\"derive\"] }\n";
+        assert_eq!(validate_manifest(manifest, &allowed(&["itertools", "proconio"])).unwrap(), "2021");
+        assert_eq!(validate_manifest("", &allowed(&[])).unwrap(), "2018");
+    }
+
+    #[test]
+    fn manifest_rejects_other_sections_and_keys() {
+        assert!(manifest_error("[build-dependencies]\ncc = \"1\"\n").contains("[build-dependencies] is not allowed"));
+        assert!(manifest_error("[profile.release]\nopt-level = 0\n").contains("[profile] is not allowed"));
+        assert!(manifest_error("[package]\nbuild = \"build.rs\"\n").contains("package.build is not allowed"));
+        assert!(manifest_error("[package]\nedition = \"2024\"\n").contains("unsupported edition 2024"));
+        assert!(manifest_error("[package]\nedition = 2021\n").contains("package.edition must be a string"));
+        assert!(manifest_error("[dependencies]\nrand = \"0.8\"\n").contains("dependency rand is not available"));
+        assert!(manifest_error("[package\n").starts_with("Cargo.toml: "));
+    }
+
+    #[test]
+    fn projects_without_externs_cannot_use_dependencies() {
+        init_test_config();
+        let profile = CompileProfile { codegen_options: vec![], flags: vec![], externs: false, toolchain: None };
+        let files = |entries: &[(&str, &str)]| entries.iter().map(|(path, content)| (path.to_string(), content.to_string()) ).collect::<BTreeMap<_, _>>();
+
+        let error = save_project("test-project", &files(&[("Cargo.toml", "[dependencies]\nitertools = \"*\"\n"), ("src/main.rs", "")]), &profile).err().unwrap();
+        assert!(error.to_string().contains("dependency itertools is not available"), "{error}");
+        let error = save_project("test-project", &files(&[("src/lib.rs", "")]), &profile).err().unwrap();
+        assert!(error.is::<SourceError>() && error.to_string().contains("src/main.rs is required"), "{error}");
+        let error = save_project("test-project", &files(&[("src/main.rs", ""), ("build.rs", "")]), &profile).err().unwrap();
+        assert!(error.to_string().contains("must be placed under src/"), "{error}");
+    }
+}
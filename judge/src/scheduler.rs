@@ -0,0 +1,197 @@
+use std::collections::*;
+
+use serde::{Deserialize, Serialize};
+
+use crate::SubmissionData;
+use crate::config::*;
+
+/// 提出の優先度
+///
+/// 優先度の高い提出が待機していれば、低い提出より先にジャッジする。
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Priority {
+    Contest,
+    #[default]
+    Practice,
+    Rejudge,
+}
+
+/// 重み 1 のクライアントの提出 1 件あたりの仮想時間
+const COST_SCALE: u64 = 1 << 20;
+
+/// 待機中の提出のキュー
+///
+/// 優先度ごとに、クライアント間で重み付き公平キューイング (WFQ) を行う。
+/// 各提出には到着時に仮想的な終了時刻を割り当て、その小さい順に取り出すため、
+/// 一つのクライアントが大量に提出しても他のクライアントの提出は重みに応じた割合で処理される。
+#[derive(Default)]
+pub struct Scheduler {
+    classes: BTreeMap<Priority, FairQueue>,
+    sequence: u64,
+}
+
+#[derive(Default)]
+struct FairQueue {
+    /// (仮想終了時刻, 到着順) → 提出
+    items: BTreeMap<(u64, u64), SubmissionData>,
+    /// クライアントごとの最後の提出の仮想終了時刻
+    finish_times: HashMap<String, u64>,
+    virtual_time: u64,
+}
+
+impl Scheduler {
+    pub fn push(&mut self, submission_data: SubmissionData) {
+        let class = self.classes.entry(submission_data.priority).or_default();
+        let weight = client_weight(&submission_data.client);
+        let last_finish = class.finish_times.get(&submission_data.client).copied().unwrap_or(0);
+        let finish = class.virtual_time.max(last_finish) + COST_SCALE / weight;
+        class.finish_times.insert(submission_data.client.clone(), finish);
+        class.items.insert((finish, self.sequence), submission_data);
+        self.sequence += 1;
+    }
+
+    pub fn pop(&mut self) -> Option<SubmissionData> {
+        let class = self.classes.values_mut().find(|class| !class.items.is_empty() )?;
+        let ((finish, _), submission_data) = class.items.pop_first()?;
+        class.virtual_time = finish;
+        // 待機中の提出がなくなったクライアントの記録は不要になる
+        class.finish_times.retain(|_, &mut last_finish| last_finish > finish );
+        Some(submission_data)
+    }
+
+    pub fn remove(&mut self, submission_id: &str) -> Option<SubmissionData> {
+        for class in self.classes.values_mut() {
+            let key = class.items.iter().find(|(_, submission_data)| submission_data.submission_id == submission_id ).map(|(&key, _)| key );
+            if let Some(key) = key {
+                return class.items.remove(&key);
+            }
+        }
+        None
+    }
+
+    /// 先にジャッジされる待機中の提出の数
+    pub fn position(&self, submission_id: &str) -> Option<usize> {
+        self.iter().position(|submission_data| submission_data.submission_id == submission_id )
+    }
+
+    pub fn len(&self) -> usize {
+        self.classes.values().map(|class| class.items.len() ).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// 取り出される順に列挙する
+    pub fn iter(&self) -> impl Iterator<Item = &SubmissionData> {
+        self.classes.values().flat_map(|class| class.items.values() )
+    }
+}
+
+fn client_weight(client: &str) -> u64 {
+    let scheduler = &CONFIG.server.scheduler;
+    scheduler.client_weights.get(client).copied().unwrap_or(scheduler.default_weight).max(1)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+
+    use super::*;
+    use crate::program::Source;
+
+    fn submission(id: &str, client: &str, priority: Priority) -> SubmissionData {
+        SubmissionData {
+            submitted_time: Instant::now(),
+            submission_id: id.to_string(),
+            source: Source::Single(String::new()),
+            profile: "release".to_string(),
+            inputs: vec![],
+            priority,
+            client: client.to_string(),
+            problem: None,
+        }
+    }
+
+    fn scheduler(submissions: &[(&str, &str, Priority)]) -> Scheduler {
+        init_test_config();
+        let mut scheduler = Scheduler::default();
+        for &(id, client, priority) in submissions {
+            scheduler.push(submission(id, client, priority));
+        }
+        scheduler
+    }
+
+    fn drain(scheduler: &mut Scheduler) -> Vec<String> {
+        std::iter::from_fn(|| scheduler.pop() ).map(|submission_data| submission_data.submission_id ).collect()
+    }
+
+    #[test]
+    fn higher_priority_goes_first() {
+        let mut scheduler = scheduler(&[
+            ("rejudge", "a", Priority::Rejudge),
+            ("practice", "a", Priority::Practice),
+            ("contest", "b", Priority::Contest),
+        ]);
+        assert_eq!(drain(&mut scheduler), ["contest", "practice", "rejudge"]);
+    }
+
+    #[test]
+    fn clients_alternate_within_a_priority() {
+        let mut scheduler = scheduler(&[
+            ("a1", "a", Priority::Practice),
+            ("a2", "a", Priority::Practice),
+            ("a3", "a", Priority::Practice),
+            ("a4", "a", Priority::Practice),
+            ("b1", "b", Priority::Practice),
+            ("b2", "b", Priority::Practice),
+        ]);
+        assert_eq!(drain(&mut scheduler), ["a1", "b1", "a2", "b2", "a3", "a4"]);
+    }
+
+    #[test]
+    fn weights_set_the_share() {
+        // heavy の重みは 2
+        let mut scheduler = scheduler(&[
+            ("a1", "a", Priority::Practice),
+            ("a2", "a", Priority::Practice),
+            ("h1", "heavy", Priority::Practice),
+            ("h2", "heavy", Priority::Practice),
+            ("h3", "heavy", Priority::Practice),
+            ("h4", "heavy", Priority::Practice),
+        ]);
+        assert_eq!(drain(&mut scheduler), ["h1", "a1", "h2", "h3", "a2", "h4"]);
+    }
+
+    #[test]
+    fn late_client_does_not_get_credit_for_idle_time() {
+        let mut scheduler = scheduler(&[
+            ("a1", "a", Priority::Practice),
+            ("a2", "a", Priority::Practice),
+            ("a3", "a", Priority::Practice),
+        ]);
+        assert_eq!(scheduler.pop().unwrap().submission_id, "a1");
+        assert_eq!(scheduler.pop().unwrap().submission_id, "a2");
+        // b が待っていなかった間の分を取り戻して a を追い越すことはない
+        scheduler.push(submission("b1", "b", Priority::Practice));
+        scheduler.push(submission("b2", "b", Priority::Practice));
+        assert_eq!(drain(&mut scheduler), ["a3", "b1", "b2"]);
+    }
+
+    #[test]
+    fn position_and_remove_follow_pop_order() {
+        let mut scheduler = scheduler(&[
+            ("practice", "a", Priority::Practice),
+            ("contest", "a", Priority::Contest),
+            ("other", "b", Priority::Practice),
+        ]);
+        assert_eq!(scheduler.position("contest"), Some(0));
+        assert_eq!(scheduler.position("other"), Some(2));
+        assert_eq!(scheduler.remove("practice").map(|submission_data| submission_data.submission_id ), Some("practice".to_string()));
+        assert_eq!(scheduler.remove("practice").map(|submission_data| submission_data.submission_id ), None);
+        assert_eq!(scheduler.len(), 2);
+        assert_eq!(drain(&mut scheduler), ["contest", "other"]);
+        assert!(scheduler.is_empty());
+    }
+}
@@ -5,131 +5,433 @@ use std::sync::mpsc::*;
 use std::time::Duration;
 use std::time::Instant;
 
-use crate::server::compile::CompilingResult;
-use crate::{SubmissionData, SubmissionStatus};
+use tracing::{error, info, info_span, warn};
+
+use crate::server::compile::{find_profile, CompilingResult};
+use crate::server::execute::{ExecutionResult, KillHandle, Verdict};
+use crate::{JudgeStatus, SubmissionData, SubmissionStatus};
+use crate::{auth, config, contest, logging};
+use crate::contest::ContestSubmission;
+use crate::metrics::{label, METRICS};
+use crate::problem::{check_output, Problem, ProblemVerdict};
 use crate::program::*;
+use crate::scheduler::Scheduler;
 use crate::config::*;
 
+/// 提出の取り消しの結果
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CancelResult {
+    Cancelled,
+    NotFound,
+    AlreadyFinished,
+}
+
 pub struct JudgeClient {
-    waiting_queue: Arc<Mutex<VecDeque<SubmissionData>>>,
+    waiting_queue: Arc<Mutex<Scheduler>>,
     submission_status: Arc<Mutex<HashMap<String, SubmissionStatus>>>,
+    remove_queue: Arc<Mutex<VecDeque<(Instant, String)>>>,
+    running: Arc<Mutex<Option<(String, KillHandle)>>>,
     sender: Sender<()>,
 }
+impl Default for JudgeClient {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 impl JudgeClient {
     pub fn new() -> Self {
-        let waiting_queue = Arc::new(Mutex::new(VecDeque::new()));
+        let waiting_queue = Arc::new(Mutex::new(Scheduler::default()));
         let submission_status = Arc::new(Mutex::new(HashMap::new()));
+        let remove_queue = Arc::new(Mutex::new(VecDeque::new()));
+        let running = Arc::new(Mutex::new(None));
         let (sender, receiver) = channel();
         let server = JudgeServer {
             waiting_queue: waiting_queue.clone(),
             submission_status: submission_status.clone(),
             receiver,
-            remove_queue: VecDeque::new(),
+            remove_queue: remove_queue.clone(),
+            running: running.clone(),
         };
         server.start();
         Self {
             waiting_queue,
             submission_status,
+            remove_queue,
+            running,
             sender,
         }
     }
 
     pub fn submit(&self, submission_data: SubmissionData) {
+        let _ = self.submit_checked(submission_data, |_| Ok::<_, ()>(()) );
+    }
+
+    /// クライアントの待機中またはジャッジ中の提出の数を `check` に渡し、`Ok` ならば提出をキューに入れる
+    ///
+    /// 数えてから状況に加えるまで状況のロックを持つので、同時に提出されても上限を超えて受け付けない。
+    pub fn submit_checked<E>(&self, submission_data: SubmissionData, check: impl FnOnce(usize) -> Result<(), E>) -> Result<(), E> {
         let mut statuses = self.submission_status.lock().unwrap();
+        check(statuses.values().filter(|status| status.client == submission_data.client && status.is_active() ).count())?;
+
+        info_span!("submission", submission_id = %submission_data.submission_id).in_scope(|| {
+            info!(client = %submission_data.client, priority = %label(submission_data.priority), profile = %submission_data.profile, inputs = submission_data.test_count(), problem = submission_data.problem.as_ref().map(|problem| problem.problem.id.as_str() ), "queued");
+        });
         statuses.insert(submission_data.submission_id.clone(), SubmissionStatus {
-            status: "pending".to_string(),
+            status: JudgeStatus::Pending,
             compile_result: None,
-            run_results: vec![None; submission_data.inputs.len()],
+            run_results: vec![None; submission_data.test_count()],
+            error: None,
+            queue_position: None,
+            client: submission_data.client.clone(),
+            contest: submission_data.problem.as_ref().map(|problem| problem.contest.clone() ),
+            problem: submission_data.problem.as_ref().map(|problem| problem.problem.id.clone() ),
+            verdict: None,
+            score: None,
+            test_data_version: None,
         });
 
+        METRICS.submissions.with_label_values(&[&label(JudgeStatus::Pending)]).inc();
+
         let mut queue = self.waiting_queue.lock().unwrap();
-        queue.push_back(submission_data);
+        queue.push(submission_data);
+        METRICS.queue_length.set(queue.len() as i64);
 
         self.sender.send(()).unwrap();
+        Ok(())
+    }
+
+    /// 提出を取り消す
+    ///
+    /// 待機中の提出はキューから取り除き、ジャッジ中の提出は実行中のプログラムを強制終了して残りのテストを飛ばす。
+    pub fn cancel(&self, submission_id: &str) -> CancelResult {
+        let mut statuses = self.submission_status.lock().unwrap();
+        let Some(status) = statuses.get_mut(submission_id) else { return CancelResult::NotFound };
+        if !status.is_active() {
+            return CancelResult::AlreadyFinished;
+        }
+        info_span!("submission", submission_id).in_scope(|| info!(status = %label(status.status), "cancelled") );
+        status.status = JudgeStatus::Cancelled;
+        METRICS.submissions.with_label_values(&[&label(JudgeStatus::Cancelled)]).inc();
+
+        let mut queue = self.waiting_queue.lock().unwrap();
+        let removed = queue.remove(submission_id);
+        METRICS.queue_length.set(queue.len() as i64);
+        if removed.is_none() {
+            if let Some((running_id, kill_handle)) = &*self.running.lock().unwrap() {
+                if running_id == submission_id {
+                    kill_handle.kill();
+                }
+            }
+        }
+        drop(queue);
+        drop(statuses);
+
+        // 削除キューのロックは他のロックを持たずに取る。状態とキューのロックを持ったまま取ると main の掃除とデッドロックする
+        if let Some(submission_data) = removed {
+            keep_rejudged_result(&submission_data);
+            self.remove_queue.lock().unwrap().push_back((submission_data.submitted_time, submission_data.submission_id));
+        }
+
+        CancelResult::Cancelled
     }
 
-    pub fn use_status<T>(&self, submission_id: &str, f: impl FnOnce(Option<&SubmissionStatus>) -> T) -> T {
+    /// 提出の状況を取得する
+    ///
+    /// 待機中の提出には、先にジャッジされる提出の数を設定する。
+    pub fn status(&self, submission_id: &str) -> Option<SubmissionStatus> {
         let statuses = self.submission_status.lock().unwrap();
-        f(statuses.get(submission_id))
+        let mut status = statuses.get(submission_id)?.clone();
+        if status.status == JudgeStatus::Pending {
+            status.queue_position = self.waiting_queue.lock().unwrap().position(submission_id);
+        }
+        Some(status)
     }
 }
 
 struct JudgeServer {
-    waiting_queue: Arc<Mutex<VecDeque<SubmissionData>>>,
+    waiting_queue: Arc<Mutex<Scheduler>>,
     submission_status: Arc<Mutex<HashMap<String, SubmissionStatus>>>,
-    remove_queue: VecDeque<(Instant, String)>,
+    remove_queue: Arc<Mutex<VecDeque<(Instant, String)>>>,
+    running: Arc<Mutex<Option<(String, KillHandle)>>>,
     receiver: Receiver<()>,
 }
 impl JudgeServer {
-    pub fn start(mut self) {
+    pub fn start(self) {
         thread::spawn(move || {
-            loop {
-                let _ = self.receiver.recv();
+            while self.receiver.recv().is_ok() {
                 self.main();
             }
+            error!("the judge server stopped because all clients were dropped");
         });
     }
 
-    fn main(&mut self) {
-        let mut queue = self.waiting_queue.lock().unwrap();
-        'process_submission: while let Some(submission_data) = queue.pop_front() {
-            let submission_id = &submission_data.submission_id;
+    fn main(&self) {
+        // キューのロックを持ったまま状態を更新すると submit とデッドロックするため、取り出すたびに解放する
+        let pop_submission = || {
+            let mut queue = self.waiting_queue.lock().unwrap();
+            let submission_data = queue.pop();
+            METRICS.queue_length.set(queue.len() as i64);
+            submission_data
+        };
+        while let Some(submission_data) = pop_submission() {
+            let judge_start = Instant::now();
+            METRICS.worker_busy.set(1);
+            config::pin(|| self.judge(&submission_data) );
+            if self.submission_status.lock().unwrap().get(&submission_data.submission_id).is_some_and(|status| status.status == JudgeStatus::Cancelled ) {
+                keep_rejudged_result(&submission_data);
+            }
+            METRICS.worker_busy.set(0);
+            METRICS.worker_busy_seconds.inc_by(judge_start.elapsed().as_secs_f64());
+            self.remove_queue.lock().unwrap().push_back((submission_data.submitted_time, submission_data.submission_id.clone()));
+        }
 
-            if let Some(status) = self.submission_status.lock().unwrap().get_mut(submission_id) {
-                status.status = "compiling".to_string();
+        // 削除キューのロックを持ったまま状態のロックを取ると cancel とデッドロックするため、期限切れの ID を集めてから解放する
+        let expired = {
+            let mut remove_queue = self.remove_queue.lock().unwrap();
+            let mut expired = vec![];
+            while let Some(&(submitted_time, _)) = remove_queue.front() {
+                if Instant::now() - submitted_time <= Duration::from_secs(CONFIG.server.keep_submission_time) {
+                    break;
+                }
+                expired.push(remove_queue.pop_front().unwrap().1);
             }
+            expired
+        };
+        for submission_id in expired {
+            self.submission_status.lock().unwrap().remove(&submission_id);
+            logging::remove_submission_log(&submission_id);
+        }
+    }
 
-            // コンパイル
-            let program = match Program::new(&submission_data.submission_id, &submission_data.source_code) {
-                Ok(program) => program,
-                Err(err) => {
-                    if let Some(status) = self.submission_status.lock().unwrap().get_mut(submission_id) {
-                        status.status = "compile_error".to_string();
+    fn judge(&self, submission_data: &SubmissionData) {
+        let submission_id = &submission_data.submission_id;
+        let span = info_span!("submission", submission_id = %submission_id);
+        let _enter = span.enter();
+
+        if !self.update_status(submission_id, |status| status.status = JudgeStatus::Compiling ) {
+            return;
+        }
+
+        // コンパイル
+        let compile_start = Instant::now();
+        let program = find_profile(&submission_data.profile).and_then(|profile| Program::new(submission_id, &submission_data.source, profile) );
+        METRICS.compile_duration.observe(compile_start.elapsed().as_secs_f64());
+        let program = match program {
+            Ok(program) => program,
+            Err(err) => {
+                if err.is::<SourceError>() {
+                    info!(%err, "rejected the source");
+                } else {
+                    error!(%err, "failed to compile due to an internal error");
+                }
+                let updated = self.update_status(submission_id, |status| {
+                    if err.is::<SourceError>() {
+                        status.status = JudgeStatus::CompileError;
                         status.compile_result = Some(CompilingResult {
-                            status: -1,
+                            exit: ExitInfo::default(),
                             stdout: "".to_string(),
                             stderr: err.to_string(),
                         });
+                    } else {
+                        status.status = JudgeStatus::InternalError;
+                        status.error = Some(err.to_string());
                     }
-                    continue 'process_submission
+                });
+                if updated {
+                    let verdict = if err.is::<SourceError>() { ProblemVerdict::CompileError } else { ProblemVerdict::InternalError };
+                    self.finish_problem(submission_data, None, verdict, 0);
                 }
-            };
-            
-            if let Some(status) = self.submission_status.lock().unwrap().get_mut(submission_id) {
-                status.status = "running".to_string();
+                return;
+            }
+        };
+
+        let compile_exit = &program.compile_result().exit;
+        info!(duration_ms = compile_start.elapsed().as_millis() as u64, exit_code = compile_exit.exit_code, signal = compile_exit.signal_name.as_deref(), "compiled");
+        if !compile_exit.success() {
+            let updated = self.update_status(submission_id, |status| {
+                status.status = JudgeStatus::CompileError;
                 status.compile_result = Some(program.compile_result().clone());
+            });
+            if updated {
+                self.finish_problem(submission_data, None, ProblemVerdict::CompileError, 0);
             }
-            
-            if program.compile_result().status == 0 {
-                for i in 0 .. submission_data.inputs.len() {
-                    if let Ok(result) = program.run(&submission_data.inputs[i]) {
-                        if let Some(status) = self.submission_status.lock().unwrap().get_mut(submission_id) {
-                            status.compile_result = Some(program.compile_result().clone());
-                            status.run_results[i] = Some(result);
-                        }
+            return;
+        }
+
+        // 問題への提出は、ジャッジを始めるときの問題を読み直し、ジャッジが終わるまでテストデータを更新させない
+        let (problem, _test_data_lock) = match &submission_data.problem {
+            Some(queued) => match Problem::lock(&queued.problem.id) {
+                Ok((problem, lock)) => (Some(problem), Some(lock)),
+                Err(err) => {
+                    error!(%err, "failed to load the problem");
+                    let updated = self.update_status(submission_id, |status| {
+                        status.status = JudgeStatus::InternalError;
+                        status.error = Some(format!("failed to load the problem: {err}"));
+                    });
+                    if updated {
+                        self.finish_problem(submission_data, None, ProblemVerdict::InternalError, 0);
                     }
+                    return;
                 }
+            },
+            None => (None, None),
+        };
+        let test_count = problem.as_ref().map_or(submission_data.inputs.len(), |problem| problem.tests.len() );
 
-                if let Some(status) = self.submission_status.lock().unwrap().get_mut(submission_id) {
-                        status.status = "finished".to_string();
-                }
+        let running = self.update_status(submission_id, |status| {
+            status.status = JudgeStatus::Running;
+            status.compile_result = Some(program.compile_result().clone());
+            status.run_results.resize(test_count, None);
+            status.test_data_version = problem.as_ref().map(|problem| problem.test_data_version );
+        });
+        if !running {
+            return;
+        }
+
+        let kill_handle = KillHandle::default();
+        *self.running.lock().unwrap() = Some((submission_id.clone(), kill_handle.clone()));
+
+        let mut internal_error = None;
+        let mut verdicts = vec![];
+        for i in 0 .. test_count {
+            let result = match &problem {
+                Some(problem) => run_test(&program, problem, i, &kill_handle),
+                None => run_with_retry(&program, &submission_data.inputs[i], &kill_handle),
+            };
+            verdicts.push(result.verdict);
+            // 再ジャッジの実行時間は参加者の上限に数えない
+            if submission_data.problem.as_ref().is_none_or(|problem| problem.rejudge.is_none() ) {
+                auth::record_run_time(&submission_data.client, result.time_ms.max(0) as u64);
+            }
+            METRICS.verdicts.with_label_values(&[&label(result.verdict)]).inc();
+            info!(test = i, verdict = %label(result.verdict), time_ms = result.time_ms, exit_code = result.exit.exit_code, signal = result.exit.signal_name.as_deref(), "ran a test");
+            if kill_handle.is_killed() {
+                info!(test = i, "stopped running tests because the submission was cancelled");
+                break;
+            }
+            if let (None, Some(error)) = (&internal_error, &result.error) {
+                internal_error = Some(format!("test {i}: {error}"));
+            }
+            if !self.update_status(submission_id, |status| status.run_results[i] = Some(result) ) {
+                break;
+            }
+        }
+
+        *self.running.lock().unwrap() = None;
+
+        let finished = self.update_status(submission_id, |status| {
+            if internal_error.is_some() {
+                status.status = JudgeStatus::InternalError;
+                status.error = internal_error;
             } else {
-                if let Ok(mut statuses) = self.submission_status.lock() {
-                    if let Some(status) = statuses.get_mut(&submission_data.submission_id) {
-                        status.status = "compile_error".to_string();
-                    }
+                status.status = JudgeStatus::Finished;
+            }
+            info!(status = %label(status.status), "finished judging");
+        });
+        if let (true, Some(problem)) = (finished, &problem) {
+            self.finish_problem(submission_data, Some(problem), ProblemVerdict::of(&verdicts), problem.score(&verdicts));
+        }
+    }
+
+    /// 問題への提出の判定を状況に設定し、コンテストへの提出として記録する
+    ///
+    /// `judged` はテストケースを実行した問題。テストケースを実行する前に終わったときは、提出を受け付けたときの問題とする。
+    fn finish_problem(&self, submission_data: &SubmissionData, judged: Option<&Problem>, verdict: ProblemVerdict, score: u64) {
+        let Some(problem) = &submission_data.problem else { return };
+        let judged = judged.unwrap_or(&problem.problem);
+        info!(verdict = %label(verdict), score, test_data_version = judged.test_data_version, "judged the problem submission");
+        self.update_status(&submission_data.submission_id, |status| {
+            status.verdict = Some(verdict);
+            status.score = Some(score);
+            status.test_data_version = Some(judged.test_data_version);
+        });
+        // 再ジャッジのときは、再ジャッジした提出の判定として記録する。内部エラーのときは元の判定のままにする
+        let (submission_id, rejudge_id) = match &problem.rejudge {
+            Some(rejudge) => {
+                let internal_error = self.submission_status.lock().unwrap().get(&submission_data.submission_id).is_some_and(|status| status.status == JudgeStatus::InternalError );
+                if verdict == ProblemVerdict::InternalError || internal_error {
+                    warn!(rejudge_id = rejudge.rejudge_id, submission_id = rejudge.submission_id, "kept the previous result because the rejudge failed with an internal error");
+                    contest::fail_rejudge(&rejudge.rejudge_id, &rejudge.submission_id, "internal error");
+                    return;
                 }
+                (rejudge.submission_id.clone(), Some(rejudge.rejudge_id.clone()))
             }
+            None => (submission_data.submission_id.clone(), None),
+        };
+        contest::record(ContestSubmission {
+            submission_id,
+            contest: problem.contest.clone(),
+            problem: problem.problem.id.clone(),
+            participant: problem.participant.clone(),
+            submitted_at: contest::unix_millis(problem.submitted_at),
+            verdict,
+            score,
+            test_data_version: judged.test_data_version,
+            rejudge_id,
+            previous: None,
+        });
+    }
 
-            self.remove_queue.push_back((submission_data.submitted_time, submission_data.submission_id.clone()));
+    /// 提出の状態を更新する
+    ///
+    /// 取り消された提出は更新せず、`false` を返す。
+    fn update_status(&self, submission_id: &str, f: impl FnOnce(&mut SubmissionStatus)) -> bool {
+        let mut statuses = self.submission_status.lock().unwrap();
+        match statuses.get_mut(submission_id) {
+            Some(status) if status.status != JudgeStatus::Cancelled => {
+                let previous = status.status;
+                f(status);
+                if status.status != previous {
+                    METRICS.submissions.with_label_values(&[&label(status.status)]).inc();
+                }
+                true
+            }
+            _ => false,
         }
+    }
+}
 
-        while let Some(&(submitted_time, _)) = self.remove_queue.front() {
-            if Instant::now() - submitted_time <= Duration::from_secs(CONFIG.server.keep_submission_time) {
-                break;
+/// 取り消された再ジャッジのジョブを再ジャッジの結果に記録し、元の判定のままにする
+fn keep_rejudged_result(submission_data: &SubmissionData) {
+    if let Some(rejudge) = submission_data.problem.as_ref().and_then(|problem| problem.rejudge.as_ref() ) {
+        contest::fail_rejudge(&rejudge.rejudge_id, &rejudge.submission_id, "cancelled");
+    }
+}
+
+/// 問題のテストケースで実行し、出力を確認する
+///
+/// テストケースの内容を参加者に見せないよう、出力は結果に含めない。
+fn run_test(program: &Program, problem: &Problem, test: usize, kill_handle: &KillHandle) -> ExecutionResult {
+    let (input, expected) = match problem.read_input(test).and_then(|input| Ok((input, problem.read_output(test)?)) ) {
+        Ok(test_case) => test_case,
+        Err(err) => {
+            error!(%err, problem = problem.id, test = problem.tests[test], "failed to read the test case");
+            return ExecutionResult::internal_error(format!("failed to read test case {}: {err}", problem.tests[test]));
+        }
+    };
+    let mut result = run_with_retry(program, &input, kill_handle);
+    if result.verdict == Verdict::Ok && !check_output(&result.stdout, &expected) {
+        result.verdict = Verdict::WrongAnswer;
+    }
+    result.stdout.clear();
+    result.stderr.clear();
+    result
+}
+
+/// ジャッジ側の問題で実行に失敗した場合は、設定された回数まで再試行する
+fn run_with_retry(program: &Program, input: &str, kill_handle: &KillHandle) -> ExecutionResult {
+    let mut retries = 0;
+    loop {
+        match program.run(input, kill_handle) {
+            Ok(result) => return result,
+            Err(err) if retries < CONFIG.program.internal_error_retries && !kill_handle.is_killed() => {
+                retries += 1;
+                warn!(%err, retry = retries, "failed to run the program; retrying");
+            }
+            Err(err) => {
+                error!(%err, "failed to run the program");
+                return ExecutionResult::internal_error(err.to_string());
             }
-            let submission_id = self.remove_queue.pop_front().unwrap().1;
-            self.submission_status.lock().unwrap().remove(&submission_id);
         }
     }
-}
\ No newline at end of file
+}
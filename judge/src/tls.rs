@@ -0,0 +1,133 @@
+use std::any::Any;
+use std::fs::File;
+use std::io::{self, BufReader};
+use std::sync::{Arc, Mutex, RwLock};
+
+use actix_tls::accept::rustls_0_22::TlsStream;
+use actix_web::dev::Extensions;
+use actix_web::rt::net::TcpStream;
+use once_cell::sync::Lazy;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use rustls::server::{ClientHello, ResolvesServerCert, WebPkiClientVerifier};
+use rustls::sign::CertifiedKey;
+use rustls::RootCertStore;
+
+use crate::config::*;
+
+/// 証明書を再読み込みする対象の、HTTPS の待ち受けごとの証明書
+static RESOLVERS: Lazy<Mutex<Vec<Arc<CertResolver>>>> = Lazy::new(|| Mutex::new(vec![]) );
+
+/// 検証済みのクライアント証明書のサブジェクトの CN
+#[derive(Clone, Debug)]
+pub struct ClientIdentity(pub String);
+
+/// HTTPS で待ち受けるための rustls の設定を作る
+///
+/// `client_auth` を指定した場合は、その CA が発行したクライアント証明書を検証する。
+/// 証明書と秘密鍵は `reload_certificates` で差し替えられる。
+pub fn server_config(ssl_cert_path: &str, ssl_key_path: &str, client_auth: Option<&ClientAuthConfig>) -> io::Result<rustls::ServerConfig> {
+    let resolver = Arc::new(CertResolver::new(ssl_cert_path, ssl_key_path)?);
+
+    let builder = rustls::ServerConfig::builder();
+    let builder = match client_auth {
+        None => builder.with_no_client_auth(),
+        Some(client_auth) => {
+            let mut roots = RootCertStore::empty();
+            for cert in load_certs(&client_auth.ca_path)? {
+                roots.add(cert).map_err(|err| io::Error::other(format!("{}: {err}", client_auth.ca_path)) )?;
+            }
+            let verifier = WebPkiClientVerifier::builder(Arc::new(roots));
+            let verifier = if client_auth.required { verifier } else { verifier.allow_unauthenticated() };
+            builder.with_client_cert_verifier(verifier.build().map_err(io::Error::other)?)
+        }
+    };
+    RESOLVERS.lock().unwrap().push(resolver.clone());
+    Ok(builder.with_cert_resolver(resolver))
+}
+
+/// すべての HTTPS の待ち受けの証明書と秘密鍵をファイルから読み直す
+///
+/// 確立済みの接続はそのままで、新しい接続から新しい証明書を使う。
+/// 読み込みに失敗した待ち受けは、現在の証明書のまま変わらない。
+pub fn reload_certificates() -> io::Result<()> {
+    let mut result = Ok(());
+    for resolver in RESOLVERS.lock().unwrap().iter() {
+        if let Err(err) = resolver.reload() {
+            tracing::error!(%err, "failed to reload the certificate; keeping the current certificate");
+            result = result.and(Err(err));
+        }
+    }
+    result
+}
+
+/// 差し替え可能な証明書と秘密鍵
+#[derive(Debug)]
+struct CertResolver {
+    ssl_cert_path: String,
+    ssl_key_path: String,
+    certified_key: RwLock<Arc<CertifiedKey>>,
+}
+
+impl CertResolver {
+    fn new(ssl_cert_path: &str, ssl_key_path: &str) -> io::Result<Self> {
+        Ok(Self {
+            ssl_cert_path: ssl_cert_path.to_string(),
+            ssl_key_path: ssl_key_path.to_string(),
+            certified_key: RwLock::new(Arc::new(load_certified_key(ssl_cert_path, ssl_key_path)?)),
+        })
+    }
+
+    fn reload(&self) -> io::Result<()> {
+        let certified_key = load_certified_key(&self.ssl_cert_path, &self.ssl_key_path)?;
+        *self.certified_key.write().unwrap() = Arc::new(certified_key);
+        tracing::info!(ssl_cert_path = self.ssl_cert_path, "reloaded the certificate");
+        Ok(())
+    }
+}
+
+impl ResolvesServerCert for CertResolver {
+    fn resolve(&self, _client_hello: ClientHello) -> Option<Arc<CertifiedKey>> {
+        Some(self.certified_key.read().unwrap().clone())
+    }
+}
+
+fn load_certified_key(ssl_cert_path: &str, ssl_key_path: &str) -> io::Result<CertifiedKey> {
+    let certs = load_certs(ssl_cert_path)?;
+    let key = load_private_key(ssl_key_path)?;
+    let key = rustls::crypto::ring::sign::any_supported_type(&key).map_err(|err| io::Error::other(format!("{ssl_key_path}: {err}")) )?;
+    Ok(CertifiedKey::new(certs, key))
+}
+
+/// `HttpServer::on_connect` で、接続ごとにクライアント証明書の識別子を記録する
+pub fn on_connect(connection: &dyn Any, data: &mut Extensions) {
+    let Some(stream) = connection.downcast_ref::<TlsStream<TcpStream>>() else { return };
+    let (_, session) = stream.get_ref();
+    let Some(cert) = session.peer_certificates().and_then(|certs| certs.first() ) else { return };
+    if let Some(identity) = certificate_identity(cert) {
+        data.insert(ClientIdentity(identity));
+    }
+}
+
+fn certificate_identity(cert: &CertificateDer<'_>) -> Option<String> {
+    let (_, cert) = x509_parser::parse_x509_certificate(cert.as_ref()).ok()?;
+    let common_name = cert.subject().iter_common_name().next()?;
+    common_name.as_str().ok().map(str::to_string)
+}
+
+fn open(path: &str) -> io::Result<BufReader<File>> {
+    File::open(path).map(BufReader::new).map_err(|err| io::Error::new(err.kind(), format!("{path}: {err}")) )
+}
+
+fn load_certs(path: &str) -> io::Result<Vec<CertificateDer<'static>>> {
+    let certs = rustls_pemfile::certs(&mut open(path)?).collect::<Result<Vec<_>, _>>()?;
+    if certs.is_empty() {
+        return Err(io::Error::other(format!("{path}: no certificate found")));
+    }
+    Ok(certs)
+}
+
+/// PKCS#8, PKCS#1 (RSA), SEC1 (EC) のいずれかの形式の秘密鍵を読み込む
+fn load_private_key(path: &str) -> io::Result<PrivateKeyDer<'static>> {
+    rustls_pemfile::private_key(&mut open(path)?)?
+        .ok_or_else(|| io::Error::other(format!("{path}: no PKCS#8, PKCS#1 or SEC1 private key found")) )
+}
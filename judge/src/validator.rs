@@ -0,0 +1,145 @@
+use std::error::Error;
+use std::fmt;
+use std::fs::*;
+
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::config::*;
+use crate::problem::{problem_file_path, ProblemManifest, TestDataError};
+use crate::program::compile::find_profile;
+use crate::program::execute::{KillHandle, Verdict};
+use crate::program::{Program, Source, SourceError};
+
+/// `problem.toml` の `[validator]`
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct ValidatorConfig {
+    /// 検証器のソースコード。`PUT /admin/problems/{problem_id}/files/{name}` で置いたファイルの名前
+    pub source: String,
+    /// テストケースを含む小課題ごとに、小課題の名前を引数として検証器を実行する
+    #[serde(default)]
+    pub per_subtask: bool,
+}
+
+/// 検証器がテストケースの入力を受け付けなかったことを表すエラー
+#[derive(Clone, Debug, Serialize)]
+pub struct ValidationFailure {
+    pub test: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub subtask: Option<String>,
+    /// 検証器が報告した、失敗した位置（1 始まり）
+    pub line: Option<u64>,
+    pub column: Option<u64>,
+    pub message: String,
+}
+
+impl fmt::Display for ValidationFailure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "validator rejected {}.in", self.test)?;
+        if let Some(subtask) = &self.subtask {
+            write!(f, " (subtask {subtask})")?;
+        }
+        if let (Some(line), Some(column)) = (self.line, self.column) {
+            write!(f, " at line {line}, column {column}")?;
+        }
+        write!(f, ": {}", self.message)
+    }
+}
+
+impl Error for ValidationFailure {}
+
+/// コンパイルした検証器
+///
+/// 検証器は入力を標準入力から読み、正しければ終了コード 0 で終了する。
+/// 正しくなければ 0 以外で終了し、標準エラー出力の最初の行に `<行>:<列>: <メッセージ>` の形で失敗した位置を書く。
+pub struct Validator {
+    program: Program,
+    per_subtask: bool,
+}
+
+impl Validator {
+    /// 問題の検証器をコンパイルする。検証器がなければ `None` を返す
+    pub fn compile(problem_id: &str, manifest: &ProblemManifest) -> Result<Option<Self>, Box<dyn Error>> {
+        let Some(config) = &manifest.validator else { return Ok(None) };
+        let source = read_to_string(problem_file_path(problem_id, &config.source)?)
+            .map_err(|err| TestDataError(format!("failed to read the validator {}: {err}", config.source)) )?;
+        let profile = find_profile(&CONFIG.program.default_profile)?;
+        let program = match Program::new(&format!("validator-{}", Uuid::new_v4().hyphenated()), &Source::Single(source), profile) {
+            Ok(program) => program,
+            Err(err) if err.is::<SourceError>() => return Err(TestDataError(format!("invalid validator: {err}")).into()),
+            Err(err) => return Err(err),
+        };
+        let compile_result = program.compile_result();
+        if !compile_result.exit.success() {
+            return Err(TestDataError(format!("failed to compile the validator:\n{}", compile_result.error_message())).into());
+        }
+        Ok(Some(Self { program, per_subtask: config.per_subtask }))
+    }
+
+    /// テストケースの入力を検証する
+    pub fn validate(&self, manifest: &ProblemManifest, test: &str, input: &str) -> Result<(), Box<dyn Error>> {
+        let mut subtasks = vec![];
+        if self.per_subtask {
+            subtasks.extend(manifest.subtasks.iter().filter(|subtask| subtask.contains(test) ).map(|subtask| Some(subtask.name.clone()) ));
+        }
+        if subtasks.is_empty() {
+            subtasks.push(None);
+        }
+
+        for subtask in subtasks {
+            let args = subtask.iter().cloned().collect::<Vec<_>>();
+            let result = self.program.run_with_args(input, &args, &KillHandle::default())?;
+            let (line, column, message) = match result.verdict {
+                Verdict::Ok => continue,
+                Verdict::TimeLimitExceeded => (None, None, "the validator exceeded the time limit".to_string()),
+                _ => match (result.exit.exit_code, result.exit.signal_name) {
+                    (Some(exit_code), _) => parse_message(&result.stderr).unwrap_or_else(|| (None, None, format!("the validator exited with code {exit_code}")) ),
+                    (None, signal_name) => (None, None, format!("the validator was killed by {}", signal_name.as_deref().unwrap_or("a signal"))),
+                },
+            };
+            return Err(ValidationFailure { test: test.to_string(), subtask, line, column, message }.into());
+        }
+        Ok(())
+    }
+}
+
+/// 標準エラー出力の最初の空でない行を読む。`<行>:<列>: ` で始まらなければ、行全体をメッセージとする
+fn parse_message(stderr: &str) -> Option<(Option<u64>, Option<u64>, String)> {
+    let first_line = stderr.lines().map(str::trim).find(|line| !line.is_empty() )?;
+    let position = first_line.split_once(':').and_then(|(line, rest)| {
+        let (column, message) = rest.split_once(':')?;
+        Some((line.trim().parse().ok()?, column.trim().parse().ok()?, message.trim().to_string()))
+    });
+    Some(match position {
+        Some((line, column, message)) => (Some(line), Some(column), message),
+        None => (None, None, first_line.to_string()),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn message_with_position() {
+        assert_eq!(
+            parse_message("\n  3:14: expected an integer\nsecond line\n"),
+            Some((Some(3), Some(14), "expected an integer".to_string())),
+        );
+    }
+
+    #[test]
+    fn message_without_position() {
+        assert_eq!(parse_message("n is out of range"), Some((None, None, "n is out of range".to_string())));
+        assert_eq!(parse_message("line 3: bad"), Some((None, None, "line 3: bad".to_string())));
+        assert_eq!(parse_message("3: bad"), Some((None, None, "3: bad".to_string())));
+        assert_eq!(parse_message("a:1: bad"), Some((None, None, "a:1: bad".to_string())));
+    }
+
+    #[test]
+    fn empty_stderr_has_no_message() {
+        assert_eq!(parse_message(""), None);
+        assert_eq!(parse_message(" \n\t\n"), None);
+    }
+}